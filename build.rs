@@ -0,0 +1,22 @@
+use std::process::Command;
+
+// Exposes the git commit as GIT_SHA for the readiness probe's build info.
+// Builds without a git checkout, e.g. in Docker, can pass GIT_SHA through the environment.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let git_sha = match std::env::var("GIT_SHA") {
+        Ok(sha) if !sha.is_empty() => sha,
+        _ => Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_string())
+            .unwrap_or("unknown".to_string()),
+    };
+    println!("cargo:rustc-env=GIT_SHA={git_sha}");
+}
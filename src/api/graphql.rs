@@ -0,0 +1,189 @@
+use actix_web::{
+    post,
+    web::{Data, ReqData},
+};
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, Object, Result, Schema,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use chrono::NaiveDate;
+use mongodb::bson::oid::ObjectId;
+
+use super::routes_structs::BookingPayload;
+use crate::handlers::mongo::MongoDB;
+use crate::middlewares::auth::UserId;
+use crate::models::mongo::{Day, EventDocument};
+use crate::services::booking::{
+    apply_booking, apply_deletion, find_owned_event, plan_booking, plan_deletion, BookingError,
+};
+use crate::services::booking_rules::BookingRules;
+
+// Query limits, the nesting of days -> events -> bookingDetails needs a depth of 4
+const MAX_QUERY_DEPTH: usize = 6;
+const MAX_QUERY_COMPLEXITY: usize = 500;
+
+// Upper bound for the number of days a single days query may span
+const MAX_QUERY_DAYS: i64 = 62;
+
+pub type BookingSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn build_schema(db: Data<MongoDB>, rules: Data<BookingRules>) -> BookingSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(db)
+        .data(rules)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+// The schema is served behind the JWT middleware, the user ID is passed on to the resolvers
+#[post("/graphql")]
+pub async fn graphql(
+    schema: Data<BookingSchema>,
+    user_id: ReqData<UserId>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    schema
+        .execute(request.into_inner().data(user_id.into_inner()))
+        .await
+        .into()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The user's days in the given range, in YYYY-MM-DD format, of at most 62 days
+    async fn days(&self, ctx: &Context<'_>, from: String, to: String) -> Result<Vec<Day>> {
+        let range_ok = match (
+            NaiveDate::parse_from_str(&from, "%Y-%m-%d"),
+            NaiveDate::parse_from_str(&to, "%Y-%m-%d"),
+        ) {
+            (Ok(from), Ok(to)) => from <= to && (to - from).num_days() < MAX_QUERY_DAYS,
+            _ => false,
+        };
+        if !range_ok {
+            return Err(invalid_payload(
+                "Invalid date range. Required date format: YYYY-MM-DD, range of at most 62 days",
+            ));
+        }
+        let UserId(user_id) = ctx.data::<UserId>()?;
+        let db = ctx.data::<Data<MongoDB>>()?;
+
+        db.find_days(user_id, &from, &to)
+            .await
+            .map_err(|err| database_error("An error occurred while fetching the days!", err))
+    }
+
+    /// The user's day, in YYYY-MM-DD format
+    async fn day(&self, ctx: &Context<'_>, day: String) -> Result<Option<Day>> {
+        let days = self.days(ctx, day.clone(), day).await?;
+        Ok(days.into_iter().next())
+    }
+
+    /// The user's event, on one of their days
+    async fn event(&self, ctx: &Context<'_>, id: ObjectId) -> Result<Option<EventDocument>> {
+        let UserId(user_id) = ctx.data::<UserId>()?;
+        let db = ctx.data::<Data<MongoDB>>()?;
+
+        match find_owned_event(db, user_id, &id.to_hex()).await {
+            Ok((event, _)) => Ok(Some(event)),
+            Err(BookingError::EventNotFound) => Ok(None),
+            Err(err) => Err(booking_error(err)),
+        }
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Books hours of an event onto a day, in YYYY-MM-DD format. Amount must be at least 0.25h
+    async fn book_event(
+        &self,
+        ctx: &Context<'_>,
+        event_id: ObjectId,
+        day: String,
+        amount: f32,
+    ) -> Result<Option<EventDocument>> {
+        let payload = BookingPayload {
+            eventId: event_id.to_hex(),
+            day,
+            amount: amount.to_string(),
+            dryRun: None,
+        };
+        if !payload.validate() {
+            return Err(invalid_payload(
+                "Invalid date format or amount. Required date format: YYYY-MM-DD. Amount must be at least 0.25h",
+            ));
+        }
+        let UserId(user_id) = ctx.data::<UserId>()?;
+        let db = ctx.data::<Data<MongoDB>>()?;
+        let rules = ctx.data::<Data<BookingRules>>()?;
+
+        let plan = plan_booking(db, rules, user_id, &payload.eventId, &payload.day, amount)
+            .await
+            .map_err(booking_error)?;
+        apply_booking(db, user_id, plan)
+            .await
+            .map_err(booking_error)
+    }
+
+    async fn delete_booking(
+        &self,
+        ctx: &Context<'_>,
+        booking_id: ObjectId,
+    ) -> Result<EventDocument> {
+        let UserId(user_id) = ctx.data::<UserId>()?;
+        let db = ctx.data::<Data<MongoDB>>()?;
+
+        let plan = plan_deletion(db, user_id, &booking_id.to_hex())
+            .await
+            .map_err(booking_error)?;
+        apply_deletion(db, user_id, plan)
+            .await
+            .map_err(booking_error)
+    }
+}
+
+#[ComplexObject]
+impl Day {
+    #[graphql(name = "events")]
+    async fn event_documents(&self, ctx: &Context<'_>) -> Result<Vec<EventDocument>> {
+        let db = ctx.data::<Data<MongoDB>>()?;
+
+        db.find_events_by_ids(&self.events)
+            .await
+            .map_err(|err| database_error("An error occurred while fetching the events!", err))
+    }
+}
+
+#[ComplexObject]
+impl EventDocument {
+    #[graphql(name = "day")]
+    async fn day_document(&self, ctx: &Context<'_>) -> Result<Option<Day>> {
+        let UserId(user_id) = ctx.data::<UserId>()?;
+        let db = ctx.data::<Data<MongoDB>>()?;
+
+        // Days of other users are left out, like in the days query
+        let day = db
+            .find_day_by_id(self.day)
+            .await
+            .map_err(|err| database_error("An error occurred while fetching the day!", err))?;
+        Ok(day.filter(|day| day.owner.to_hex() == *user_id))
+    }
+}
+
+// Errors carry the same codes as the REST responses in their extensions
+fn booking_error(err: BookingError) -> async_graphql::Error {
+    let code = err.code();
+    async_graphql::Error::new(err.to_string()).extend_with(|_, e| e.set("code", code))
+}
+
+fn invalid_payload(message: &str) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", "INVALID_PAYLOAD"))
+}
+
+fn database_error(message: &'static str, err: mongodb::error::Error) -> async_graphql::Error {
+    booking_error(BookingError::Database(message, err))
+}
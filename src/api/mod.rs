@@ -1,3 +1,6 @@
+pub mod graphql;
+pub mod openapi;
 pub mod routes;
+pub mod routes_config;
 pub mod routes_helpers;
 pub mod routes_structs;
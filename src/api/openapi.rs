@@ -0,0 +1,72 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::routes;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "metrics_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Booking Machine",
+        description = "Books the worked hours of events onto days."
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        routes::health,
+        routes::livez,
+        routes::readyz,
+        routes::metrics,
+        routes::book_event,
+        routes::delete_event,
+        routes::create_booking,
+        routes::list_bookings,
+        routes::get_booking,
+        routes::update_booking,
+        routes::delete_booking,
+        routes::distribute_event,
+        routes::booking_report,
+        routes::export_bookings,
+        routes::calendar_feed,
+        routes::public_calendar_feed,
+        routes::create_feed_token,
+        routes::import_bookings,
+        routes::booking_notifications,
+        routes::create_webhook,
+        routes::list_webhooks,
+        routes::delete_webhook,
+        routes::webhook_deliveries,
+        routes::consistency_check,
+    ),
+    modifiers(&SecurityAddon),
+    security(("bearer_auth" = [])),
+    tags(
+        (name = "bookings", description = "Booking and deleting hours"),
+        (name = "reports", description = "Reports, exports and calendar feeds"),
+        (name = "webhooks", description = "Webhooks called on booking changes"),
+        (name = "health", description = "Service status"),
+        (name = "admin", description = "Maintenance of the stored data"),
+    )
+)]
+pub struct ApiDoc;
@@ -1,28 +1,185 @@
+use std::time::{Duration, Instant};
+
 use actix_web::{
-    delete, get, post,
-    web::{Data, Query, ReqData},
-    HttpResponse,
+    delete, get,
+    http::header,
+    patch, post,
+    rt::time::timeout,
+    web::{Bytes, Data, Json, Path, Query, ReqData},
+    HttpRequest, HttpResponse,
 };
+use chrono::NaiveDate;
+use mongodb::bson::{oid::ObjectId, DateTime};
 // use futures::join;
-use mongodb::bson::oid::ObjectId;
+use tracing::warn;
 
 use super::routes_structs::{
-    BookingPayload, DeleteBookingPayload, ErrorResPayload, EventResPayload,
+    BookingDetailResPayload, BookingDetailsResPayload, BookingPayload, BookingResPayload,
+    BuildInfo, ConsistencyPayload, DeleteBookingPayload, DeliveriesPayload, DependencyStatus,
+    DistributionPayload, DistributionRequest, DistributionResPayload, DryRunPayload,
+    DryRunResPayload, ErrorResPayload, EventResPayload, ExportPayload, FeedTokenPayload,
+    FeedTokenResPayload, ImportPayload, NewBookingPayload, NewWebhookPayload, NotificationsPayload,
+    ObjectIdPath, ReadinessResPayload, ReportPayload, ReportResPayload, UpdateBookingPayload,
+    WebhookDeliveriesResPayload, WebhookInfo, WebhookResPayload, WebhooksResPayload,
+};
+use super::{
+    routes_helpers::{bearer_token_matches, booking_error_response, compare},
+    routes_structs::Health,
+};
+
+use crate::config::Config;
+use crate::handlers::mongo::{EventBookings, MongoDB};
+use crate::middlewares::auth::{UserId, UserObjectId};
+use crate::models::mongo::{BookingDetail, EventDocument, Webhook};
+use crate::models::report::BookingReport;
+use crate::models::schema::ObjectIdSchema;
+use crate::services::booking::{
+    apply_booking, apply_deletion, apply_update, find_owned_booking, find_owned_event,
+    plan_booking, plan_deletion, plan_update, BookingError,
 };
-use super::{routes_helpers::compare, routes_structs::Health};
+use crate::services::booking_rules::BookingRules;
+use crate::services::consistency::{check_consistency, ConsistencyReport};
+use crate::services::distribution::{candidate_days, distribute, DayCapacity};
+use crate::services::export::{csv_stream, file_stream, write_xlsx};
+use crate::services::ical::{hash_feed_token, new_feed_token, render_calendar};
+use crate::services::import::{import_rows, parse_rows, ImportReport};
+use crate::services::metrics::Metrics;
+use crate::services::notifications::{sse_stream, BookingNotification};
+use crate::services::outbox::booking_created_entries;
+use crate::services::webhooks::{check_webhook_url, new_webhook_secret};
 
-use crate::handlers::mongo::MongoDB;
-use crate::middlewares::auth::UserId;
-use crate::models::mongo::BookingDetail;
+// How long the readiness probe waits for a dependency before reporting it as down
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
 
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "Service is up", body = Health))
+)]
 #[get("/health")]
 pub async fn health() -> HttpResponse {
     HttpResponse::Ok().json(Health { status: "ok" })
 }
 
+// The probes are mounted at the root, outside of /api/v1, and are public
+#[utoipa::path(
+    tag = "health",
+    servers((url = "/")),
+    security(()),
+    responses((status = 200, description = "The process is running", body = Health))
+)]
+#[get("/livez")]
+pub async fn livez() -> HttpResponse {
+    HttpResponse::Ok().json(Health { status: "ok" })
+}
+
+#[utoipa::path(
+    tag = "health",
+    servers((url = "/")),
+    security(()),
+    responses(
+        (status = 200, description = "All dependencies are up", body = ReadinessResPayload),
+        (status = 503, description = "At least one dependency is down", body = ReadinessResPayload),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(db: Data<MongoDB>) -> HttpResponse {
+    let started = Instant::now();
+    // The errors are only logged, they may reveal internals of the deployment
+    let status = match timeout(READINESS_TIMEOUT, db.ping()).await {
+        Ok(Ok(_)) => "up",
+        Ok(Err(err)) => {
+            warn!("Readiness check failed, MongoDB ping error: {err}");
+            "down"
+        }
+        Err(_) => {
+            warn!(
+                "Readiness check failed, MongoDB didn't respond within {}ms",
+                READINESS_TIMEOUT.as_millis()
+            );
+            "down"
+        }
+    };
+    let dependencies = vec![DependencyStatus {
+        name: "mongodb",
+        status,
+        latencyMs: started.elapsed().as_millis() as u64,
+    }];
+
+    let ready = dependencies
+        .iter()
+        .all(|dependency| dependency.status == "up");
+    let payload = ReadinessResPayload {
+        status: if ready { "ok" } else { "degraded" },
+        dependencies,
+        build: BuildInfo::current(),
+    };
+    if ready {
+        HttpResponse::Ok().json(payload)
+    } else {
+        HttpResponse::ServiceUnavailable().json(payload)
+    }
+}
+
+#[utoipa::path(
+    tag = "health",
+    servers((url = "/")),
+    security(("metrics_token" = [])),
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain", body = String),
+        (status = 401, description = "Missing or invalid metrics token", body = ErrorResPayload),
+        (status = 404, description = "Metrics are disabled, no metrics token is configured", body = ErrorResPayload),
+    )
+)]
+#[get("/metrics")]
+pub async fn metrics(
+    req: HttpRequest,
+    config: Data<Config>,
+    metrics: Data<Metrics>,
+) -> HttpResponse {
+    // Exposing the metrics is opt-in, they reveal the traffic and the routes of the service
+    let token = match &config.metrics_token {
+        Some(token) => token,
+        None => {
+            return HttpResponse::NotFound().json(ErrorResPayload::new(
+                "An error occurred!".to_string(),
+                "Metrics are disabled".to_string(),
+            ))
+        }
+    };
+    if !bearer_token_matches(&req, token) {
+        return HttpResponse::Unauthorized().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Missing or invalid metrics token".to_string(),
+        ));
+    }
+
+    match metrics.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(body),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(BookingPayload),
+    responses(
+        (status = 200, description = "Booking completed, or the would-be changes in dry-run mode", body = BookingResPayload<EventDocument>),
+        (status = 400, description = "Amount exceeds the event's or the day's available hours", body = ErrorResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Event not found", body = ErrorResPayload),
+        (status = 409, description = "The event's bookings changed in the meantime, nothing was saved", body = ErrorResPayload),
+        (status = 422, description = "Invalid payload or booking day", body = ErrorResPayload),
+    )
+)]
 #[post("/book")]
 pub async fn book_event(
     db: Data<MongoDB>,
+    rules: Data<BookingRules>,
     query: Query<BookingPayload>,
     user_id: ReqData<UserId>,
 ) -> HttpResponse {
@@ -37,72 +194,486 @@ pub async fn book_event(
 
     let amount: f32 = query.amount.parse().unwrap_or_default();
 
-    let event = match db.find_event_by_id(&query.eventId).await {
-        Ok(event) => match event {
-            Some(event_doc) => event_doc,
-            None => {
-                return HttpResponse::NotFound().json(ErrorResPayload::new(
-                    "An error occurred!".to_string(),
-                    "Event not found".to_string(),
-                ))
-            }
-        },
-        Err(err) => {
-            return HttpResponse::InternalServerError().json(ErrorResPayload::new(
-                "An error occurred while fetching the event!".to_string(),
-                err.to_string(),
-            ))
-        }
+    let plan = match plan_booking(&db, &rules, &user_id, &query.eventId, &query.day, amount).await {
+        Ok(plan) => plan,
+        Err(err) => return booking_error_response(err),
     };
 
-    // Construct the new BookingDetail object
-    let booking_detail = BookingDetail::new(query.day.to_string(), amount);
+    if query.dryRun.unwrap_or(false) {
+        return HttpResponse::Ok().json(BookingResPayload::DryRun(DryRunResPayload::new(
+            "Booking validated, nothing was saved.".to_string(),
+            plan.available_hours(),
+            false,
+            plan.day_change.clone().into_iter().collect(),
+            Some(plan.event),
+        )));
+    }
+
+    match apply_booking(&db, &user_id, plan).await {
+        Ok(event_opt) => HttpResponse::Ok().json(BookingResPayload::Completed(
+            EventResPayload::new("Booking completed.".to_string(), event_opt),
+        )),
+        Err(err) => booking_error_response(err),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(DeleteBookingPayload),
+    responses(
+        (status = 200, description = "Booking detail deleted, or the would-be changes in dry-run mode", body = BookingResPayload<EventDocument>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Booking detail not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid payload", body = ErrorResPayload),
+    )
+)]
+#[delete("/delete")]
+pub async fn delete_event(
+    db: Data<MongoDB>,
+    query: Query<DeleteBookingPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    // TODO: Move validation to middleware?
+    if !query.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid ID format!".to_string(),
+        ));
+    }
+
+    let UserId(user_id) = user_id.into_inner();
 
-    // Set the initial duration booked to the submitted amount
-    let mut duration_booked = booking_detail.amount;
+    let DeleteBookingPayload {
+        bookingId: booking_id_str,
+        dryRun: dry_run,
+    } = query.into_inner();
+
+    let plan = match plan_deletion(&db, &user_id, &booking_id_str).await {
+        Ok(plan) => plan,
+        Err(err) => return booking_error_response(err),
+    };
+
+    if dry_run.unwrap_or(false) {
+        return HttpResponse::Ok().json(BookingResPayload::DryRun(DryRunResPayload::new(
+            "Booking detail deletion validated, nothing was deleted.".to_string(),
+            plan.available_hours(),
+            plan.last_for_day,
+            plan.day_change.clone().into_iter().collect(),
+            Some(plan.event),
+        )));
+    }
 
-    if event.bookingDetails.is_some() {
-        let deets = event.bookingDetails.unwrap();
-        duration_booked = duration_booked
-            + deets
-                .iter()
-                .fold(0.0, |acc, booking_detail| acc + booking_detail.amount);
+    match apply_deletion(&db, &user_id, plan).await {
+        Ok(updated_event) => HttpResponse::Ok().json(BookingResPayload::Completed(
+            EventResPayload::new("Booking detail deleted!".to_string(), Some(updated_event)),
+        )),
+        Err(err) => booking_error_response(err),
     }
+}
 
-    // Do not allow more booking time than worked time
-    if duration_booked > event.duration {
-        return HttpResponse::BadRequest().json(ErrorResPayload::new(
+#[utoipa::path(
+    tag = "bookings",
+    params(("eventId" = ObjectIdSchema, Path, description = "ID of the event to book hours from"), DryRunPayload),
+    request_body = NewBookingPayload,
+    responses(
+        (status = 201, description = "Booking created, the Location header points to the new booking detail", body = EventResPayload<EventDocument>),
+        (status = 200, description = "The would-be changes in dry-run mode", body = DryRunResPayload<EventDocument>),
+        (status = 400, description = "Amount exceeds the event's or the day's available hours", body = ErrorResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Event not found", body = ErrorResPayload),
+        (status = 409, description = "The event's bookings changed in the meantime, nothing was saved", body = ErrorResPayload),
+        (status = 422, description = "Invalid event ID, payload or booking day", body = ErrorResPayload),
+    )
+)]
+#[post("/events/{eventId}/bookings")]
+pub async fn create_booking(
+    req: HttpRequest,
+    db: Data<MongoDB>,
+    rules: Data<BookingRules>,
+    event_id: Path<ObjectIdPath>,
+    query: Query<DryRunPayload>,
+    payload: Json<NewBookingPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    if !payload.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
             "An error occurred!".to_string(),
-            format!(
-                "Unallowed amount: {amount}h, available booking hours: {}h",
-                event.duration - (duration_booked - amount)
-            ),
+            "Invalid date format or amount. Required date format: YYYY-MM-DD. Amount must be at least 0.25h".to_string(),
         ));
     }
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(event_id) = event_id.into_inner();
 
-    let fully_booked = duration_booked == event.duration;
+    let plan = match plan_booking(
+        &db,
+        &rules,
+        &user_id,
+        &event_id.to_hex(),
+        &payload.day,
+        payload.amount,
+    )
+    .await
+    {
+        Ok(plan) => plan,
+        Err(err) => return booking_error_response(err),
+    };
 
-    // If booking is made for a different day, add the event to that day
-    if !compare(event.date, &booking_detail.toDate) {
-        match db.add_event_to_day(&user_id, &query.day, event.id).await {
-            Ok(_) => (),
-            Err(err) => {
-                return HttpResponse::InternalServerError().json(ErrorResPayload::new(
-                    "An error occurred while updating the day!".to_string(),
-                    err.to_string(),
-                ))
+    if query.dryRun.unwrap_or(false) {
+        return HttpResponse::Ok().json(DryRunResPayload::new(
+            "Booking validated, nothing was saved.".to_string(),
+            plan.available_hours(),
+            false,
+            plan.day_change.clone().into_iter().collect(),
+            Some(plan.event),
+        ));
+    }
+
+    let booking_id = plan.booking_detail.id;
+    match apply_booking(&db, &user_id, plan).await {
+        Ok(event_opt) => HttpResponse::Created()
+            .insert_header((header::LOCATION, booking_location(&req, booking_id)))
+            .json(EventResPayload::new(
+                "Booking completed.".to_string(),
+                event_opt,
+            )),
+        Err(err) => booking_error_response(err),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(("eventId" = ObjectIdSchema, Path, description = "ID of the event")),
+    responses(
+        (status = 200, description = "Booking details of the event", body = BookingDetailsResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Event not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid event ID", body = ErrorResPayload),
+    )
+)]
+#[get("/events/{eventId}/bookings")]
+pub async fn list_bookings(
+    db: Data<MongoDB>,
+    event_id: Path<ObjectIdPath>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(event_id) = event_id.into_inner();
+
+    let event = match find_owned_event(&db, &user_id, &event_id.to_hex()).await {
+        Ok((event, _)) => event,
+        Err(err) => return booking_error_response(err),
+    };
+
+    HttpResponse::Ok().json(BookingDetailsResPayload {
+        message: "Booking details fetched.".to_string(),
+        eventId: event.id,
+        bookingDetails: event.bookingDetails.unwrap_or_default(),
+    })
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(("bookingId" = ObjectIdSchema, Path, description = "ID of the booking detail")),
+    responses(
+        (status = 200, description = "The booking detail and its event's ID", body = BookingDetailResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Booking detail not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid booking ID", body = ErrorResPayload),
+    )
+)]
+#[get("/bookings/{bookingId}")]
+pub async fn get_booking(
+    db: Data<MongoDB>,
+    booking_id: Path<ObjectIdPath>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(booking_id) = booking_id.into_inner();
+
+    let event = match find_owned_booking(&db, &user_id, &booking_id.to_hex()).await {
+        Ok((event, _)) => event,
+        Err(err) => return booking_error_response(err),
+    };
+
+    let booking_detail = event
+        .bookingDetails
+        .unwrap_or_default()
+        .into_iter()
+        .find(|detail| detail.id == booking_id);
+
+    match booking_detail {
+        Some(booking_detail) => HttpResponse::Ok().json(BookingDetailResPayload {
+            message: "Booking detail fetched.".to_string(),
+            eventId: event.id,
+            bookingDetail: booking_detail,
+        }),
+        None => booking_error_response(BookingError::BookingNotFound),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(("bookingId" = ObjectIdSchema, Path, description = "ID of the booking detail"), DryRunPayload),
+    request_body = UpdateBookingPayload,
+    responses(
+        (status = 200, description = "Booking detail updated, or the would-be changes in dry-run mode", body = BookingResPayload<EventDocument>),
+        (status = 400, description = "Amount exceeds the event's or the day's available hours", body = ErrorResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Booking detail not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid booking ID, payload or booking day", body = ErrorResPayload),
+    )
+)]
+#[patch("/bookings/{bookingId}")]
+pub async fn update_booking(
+    db: Data<MongoDB>,
+    rules: Data<BookingRules>,
+    booking_id: Path<ObjectIdPath>,
+    query: Query<DryRunPayload>,
+    payload: Json<UpdateBookingPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    if !payload.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid date format or amount, or nothing to update. Required date format: YYYY-MM-DD. Amount must be at least 0.25h".to_string(),
+        ));
+    }
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(booking_id) = booking_id.into_inner();
+
+    let plan = match plan_update(
+        &db,
+        &rules,
+        &user_id,
+        &booking_id.to_hex(),
+        payload.day.as_deref(),
+        payload.amount,
+    )
+    .await
+    {
+        Ok(plan) => plan,
+        Err(err) => return booking_error_response(err),
+    };
+
+    if query.dryRun.unwrap_or(false) {
+        return HttpResponse::Ok().json(BookingResPayload::DryRun(DryRunResPayload::new(
+            "Booking detail update validated, nothing was saved.".to_string(),
+            plan.available_hours(),
+            false,
+            plan.day_changes.clone(),
+            Some(plan.event),
+        )));
+    }
+
+    match apply_update(&db, &user_id, plan).await {
+        Ok(updated_event) => HttpResponse::Ok().json(BookingResPayload::Completed(
+            EventResPayload::new("Booking detail updated.".to_string(), Some(updated_event)),
+        )),
+        Err(err) => booking_error_response(err),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(("bookingId" = ObjectIdSchema, Path, description = "ID of the booking detail"), DryRunPayload),
+    responses(
+        (status = 200, description = "Booking detail deleted, or the would-be changes in dry-run mode", body = BookingResPayload<EventDocument>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Booking detail not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid booking ID", body = ErrorResPayload),
+    )
+)]
+#[delete("/bookings/{bookingId}")]
+pub async fn delete_booking(
+    db: Data<MongoDB>,
+    booking_id: Path<ObjectIdPath>,
+    query: Query<DryRunPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(booking_id) = booking_id.into_inner();
+
+    let plan = match plan_deletion(&db, &user_id, &booking_id.to_hex()).await {
+        Ok(plan) => plan,
+        Err(err) => return booking_error_response(err),
+    };
+
+    if query.dryRun.unwrap_or(false) {
+        return HttpResponse::Ok().json(BookingResPayload::DryRun(DryRunResPayload::new(
+            "Booking detail deletion validated, nothing was deleted.".to_string(),
+            plan.available_hours(),
+            plan.last_for_day,
+            plan.day_change.clone().into_iter().collect(),
+            Some(plan.event),
+        )));
+    }
+
+    match apply_deletion(&db, &user_id, plan).await {
+        Ok(updated_event) => HttpResponse::Ok().json(BookingResPayload::Completed(
+            EventResPayload::new("Booking detail deleted!".to_string(), Some(updated_event)),
+        )),
+        Err(err) => booking_error_response(err),
+    }
+}
+
+fn booking_location(req: &HttpRequest, booking_id: ObjectId) -> String {
+    format!(
+        "{}/bookings/{}",
+        scope_prefix(req, "/events/{eventId}/bookings"),
+        booking_id.to_hex()
+    )
+}
+
+// The prefix of the scope the matched route is mounted under, e.g. /api/v1
+fn scope_prefix(req: &HttpRequest, route: &str) -> String {
+    req.match_pattern()
+        .and_then(|pattern| pattern.strip_suffix(route).map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(DistributionPayload),
+    responses(
+        (status = 200, description = "Distribution preview or committed distribution", body = DistributionResPayload<EventDocument>),
+        (status = 400, description = "Remaining hours can't be distributed", body = ErrorResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Event not found", body = ErrorResPayload),
+        (status = 409, description = "The event's bookings changed while distributing, nothing was saved", body = ErrorResPayload),
+        (status = 422, description = "Invalid payload", body = ErrorResPayload),
+    )
+)]
+#[post("/distribute")]
+pub async fn distribute_event(
+    db: Data<MongoDB>,
+    rules: Data<BookingRules>,
+    query: Query<DistributionPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let DistributionRequest { from, to, strategy } = match query.validate() {
+        Some(request) => request,
+        None => return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid event ID, date range or strategy. Required date format: YYYY-MM-DD, range of at most 62 days. Strategy must be one of: even, fillToDailyCap, weekdays".to_string(),
+        )),
+    };
+    let UserId(user_id) = user_id.into_inner();
+
+    let (event, owner) = match find_owned_event(&db, &user_id, &query.eventId).await {
+        Ok(owned_event) => owned_event,
+        Err(err) => return booking_error_response(err),
+    };
+
+    let duration_booked = match &event.bookingDetails {
+        Some(deets) => deets
+            .iter()
+            .fold(0.0, |acc, booking_detail| acc + booking_detail.amount),
+        None => 0.0,
+    };
+
+    // Only days which pass the booking rules are eligible
+    let days = candidate_days(from, to, strategy)
+        .into_iter()
+        .filter(|day| {
+            rules
+                .check(event.date, &day.format("%Y-%m-%d").to_string())
+                .is_ok()
+        })
+        .collect::<Vec<NaiveDate>>();
+
+    let daily_cap = rules.daily_cap_for(&user_id);
+    let mut capacities = vec![];
+    for day in days {
+        let available = match daily_cap {
+            Some(daily_cap) => {
+                let day_str = day.format("%Y-%m-%d").to_string();
+                match db.find_booked_amount_for_day(&user_id, &day_str).await {
+                    Ok(booked_for_day) => Some(daily_cap - booked_for_day),
+                    Err(err) => {
+                        return HttpResponse::InternalServerError().json(ErrorResPayload::new(
+                            "An error occurred while fetching the booked hours!".to_string(),
+                            err.to_string(),
+                        ))
+                    }
+                }
             }
+            None => None,
         };
+        capacities.push(DayCapacity { day, available });
+    }
+
+    let amounts = match distribute(event.duration - duration_booked, &capacities, strategy) {
+        Ok(amounts) => amounts,
+        Err(err) => {
+            return HttpResponse::BadRequest().json(ErrorResPayload::with_code(
+                "An error occurred!".to_string(),
+                err.to_string(),
+                err.code(),
+            ))
+        }
+    };
+
+    let booking_details = amounts
+        .into_iter()
+        .map(|(day, amount)| BookingDetail::new(day, amount))
+        .collect::<Vec<BookingDetail>>();
+
+    if !query.commit.unwrap_or(false) {
+        return HttpResponse::Ok().json(DistributionResPayload::<()>::new(
+            "Distribution preview.".to_string(),
+            false,
+            booking_details,
+            None,
+        ));
+    }
+
+    // Bookings made for a different day than the event's add the event to those days
+    let days = booking_details
+        .iter()
+        .filter(|booking_detail| !compare(event.date, &booking_detail.toDate))
+        .map(|booking_detail| booking_detail.toDate.clone())
+        .collect::<Vec<String>>();
+
+    let batch = [EventBookings {
+        event_id: event.id,
+        read_booking_count: event.bookingDetails.as_ref().map_or(0, Vec::len),
+        booking_details: booking_details.clone(),
+        days,
+        duration_booked: event.duration,
+        fully_booked: true,
+    }];
+
+    // The event's state after each Booking Detail of the distribution was added
+    let mut distributed_event = event.clone();
+    let mut outbox = vec![];
+    for (position, booking_detail) in booking_details.iter().enumerate() {
+        let last = position == booking_details.len() - 1;
+        distributed_event
+            .bookingDetails
+            .get_or_insert_with(Vec::new)
+            .push(booking_detail.clone());
+        distributed_event.durationBooked =
+            Some(distributed_event.durationBooked.unwrap_or_default() + booking_detail.amount);
+        distributed_event.booked = last;
+        outbox.extend(booking_created_entries(
+            owner,
+            &distributed_event,
+            booking_detail,
+            last,
+        ));
     }
 
     match db
-        .add_bookingdetail_to_event(event.id, booking_detail, duration_booked, fully_booked)
+        .add_bookingdetails_atomically(&user_id, &batch, &outbox)
         .await
     {
-        Ok(event_opt) => HttpResponse::Ok().json(EventResPayload::new(
-            "Booking completed.".to_string(),
-            event_opt,
+        Ok(Some(mut events)) => HttpResponse::Ok().json(DistributionResPayload::new(
+            "Distribution committed.".to_string(),
+            true,
+            booking_details,
+            events.pop(),
         )),
+        Ok(None) => booking_error_response(BookingError::Conflict),
         Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
             "An error occurred while updating the event!".to_string(),
             err.to_string(),
@@ -110,107 +681,543 @@ pub async fn book_event(
     }
 }
 
-#[delete("/delete")]
-pub async fn delete_event(
+#[utoipa::path(
+    tag = "reports",
+    params(ReportPayload),
+    responses(
+        (status = 200, description = "Booking report", body = ReportResPayload<BookingReport>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 422, description = "Invalid date range", body = ErrorResPayload),
+    )
+)]
+#[get("/report")]
+pub async fn booking_report(
     db: Data<MongoDB>,
-    query: Query<DeleteBookingPayload>,
+    query: Query<ReportPayload>,
     user_id: ReqData<UserId>,
 ) -> HttpResponse {
-    // TODO: Move validation to middleware?
     if !query.validate() {
         return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
             "An error occurred!".to_string(),
-            "Invalid ID format!".to_string(),
+            "Invalid date range. Required date format: YYYY-MM-DD".to_string(),
         ));
     }
-
     let UserId(user_id) = user_id.into_inner();
 
-    let DeleteBookingPayload {
-        bookingId: booking_id_str,
-    } = query.into_inner();
+    match db
+        .booking_report(&user_id, query.from.as_deref(), query.to.as_deref())
+        .await
+    {
+        Ok(report) => HttpResponse::Ok().json(ReportResPayload::new(
+            "Booking report generated.".to_string(),
+            report,
+        )),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while generating the report!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
 
-    let event = match db.find_bookingdetail_by_id(&booking_id_str).await {
-        Ok(event) => match event {
-            Some(event) => event,
-            None => {
+#[utoipa::path(
+    tag = "reports",
+    params(ExportPayload),
+    responses(
+        (status = 200, description = "Booking details as CSV or XLSX", content(
+            (String = "text/csv"),
+            (String = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+        )),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 403, description = "User is not a manager of the team", body = ErrorResPayload),
+        (status = 404, description = "Team not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid payload", body = ErrorResPayload),
+    )
+)]
+#[get("/export")]
+pub async fn export_bookings(
+    db: Data<MongoDB>,
+    query: Query<ExportPayload>,
+    user_oid: ReqData<UserObjectId>,
+) -> HttpResponse {
+    if !query.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid date range, format or team ID. Required date format: YYYY-MM-DD. Format must be one of: csv, xlsx".to_string(),
+        ));
+    }
+    let UserObjectId(user_oid) = user_oid.into_inner();
+
+    // Team exports are only available to the team's managers
+    let owners = match &query.teamId {
+        Some(team_id) => match db.find_team_by_id(team_id).await {
+            Ok(Some(team)) if team.managers.contains(&user_oid) => team.members,
+            Ok(Some(_)) => {
+                return HttpResponse::Forbidden().json(ErrorResPayload::new(
+                    "An error occurred!".to_string(),
+                    "Only team managers can export the team's bookings".to_string(),
+                ))
+            }
+            Ok(None) => {
                 return HttpResponse::NotFound().json(ErrorResPayload::new(
-                    "An error ocurred!".to_string(),
-                    "Booking detail not found!".to_string(),
+                    "An error occurred!".to_string(),
+                    "Team not found".to_string(),
+                ))
+            }
+            Err(err) => {
+                return HttpResponse::InternalServerError().json(ErrorResPayload::new(
+                    "An error occurred while fetching the team!".to_string(),
+                    err.to_string(),
                 ))
             }
         },
+        None => vec![user_oid],
+    };
+
+    let cursor = match db
+        .export_booking_rows(owners, query.from.as_deref(), query.to.as_deref())
+        .await
+    {
+        Ok(cursor) => cursor,
         Err(err) => {
             return HttpResponse::InternalServerError().json(ErrorResPayload::new(
-                "An error ocurred!".to_string(),
+                "An error occurred while exporting the bookings!".to_string(),
                 err.to_string(),
             ))
         }
     };
 
-    let duration_booked = match &event.durationBooked {
-        Some(duration_booked) => duration_booked,
-        _ => unreachable!(),
-    };
+    if query.format.as_deref() == Some("xlsx") {
+        let file = match write_xlsx(cursor).await {
+            Ok(file) => file,
+            Err(err) => {
+                return HttpResponse::InternalServerError().json(ErrorResPayload::new(
+                    "An error occurred while exporting the bookings!".to_string(),
+                    err.to_string(),
+                ))
+            }
+        };
+        return HttpResponse::Ok()
+            .content_type("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"bookings.xlsx\"",
+            ))
+            .streaming(file_stream(file));
+    }
 
-    let booking_details = match &event.bookingDetails {
-        Some(booking_details) => booking_details,
-        _ => unreachable!(),
-    };
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"bookings.csv\"",
+        ))
+        .streaming(csv_stream(cursor))
+}
 
-    let booking_detail = match booking_details
-        .iter()
-        .find(|detail| detail.id == ObjectId::parse_str(&booking_id_str).unwrap())
-    {
-        Some(booking_detail) => booking_detail,
-        _ => unreachable!(),
+#[utoipa::path(
+    tag = "reports",
+    responses(
+        (status = 200, description = "Bookings as iCalendar feed", content_type = "text/calendar", body = String),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+    )
+)]
+#[get("/calendar.ics")]
+pub async fn calendar_feed(db: Data<MongoDB>, user_oid: ReqData<UserObjectId>) -> HttpResponse {
+    let UserObjectId(user_oid) = user_oid.into_inner();
+    calendar_response(&db, user_oid).await
+}
+
+// Calendar clients can't send the Authorization header, so this route is public and authenticated by the secret feed token.
+// The token is passed in the query string, which is left out of the request logs.
+#[utoipa::path(
+    tag = "reports",
+    params(FeedTokenPayload),
+    security(()),
+    responses(
+        (status = 200, description = "Bookings as iCalendar feed", content_type = "text/calendar", body = String),
+        (status = 404, description = "Calendar feed not found", body = ErrorResPayload),
+    )
+)]
+#[get("/calendar/feed.ics")]
+pub async fn public_calendar_feed(
+    db: Data<MongoDB>,
+    query: Query<FeedTokenPayload>,
+) -> HttpResponse {
+    let feed_token = match db.find_feed_token(&hash_feed_token(&query.token)).await {
+        Ok(Some(feed_token)) => feed_token,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResPayload::new(
+                "An error occurred!".to_string(),
+                "Calendar feed not found".to_string(),
+            ))
+        }
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(ErrorResPayload::new(
+                "An error occurred while fetching the calendar feed!".to_string(),
+                err.to_string(),
+            ))
+        }
     };
 
-    let updated_duration_booked = duration_booked - booking_detail.amount;
+    calendar_response(&db, feed_token.owner).await
+}
 
-    // Check if multiple details with the same destination date exist
-    let has_more_details = booking_details
-        .iter()
-        .filter(|detail| detail.toDate == booking_detail.toDate)
-        .count()
-        > 1;
+#[utoipa::path(
+    tag = "reports",
+    responses(
+        (status = 200, description = "New calendar feed token, previous tokens are revoked", body = FeedTokenResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+    )
+)]
+#[post("/calendar/token")]
+pub async fn create_feed_token(
+    req: HttpRequest,
+    db: Data<MongoDB>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
 
-    // Delete the Booking Detail from the Event
-    let updated_event = match db
-        .remove_bookingdetail_from_event(event.id, booking_detail, updated_duration_booked)
+    let token = new_feed_token();
+    match db
+        .replace_feed_token(&user_id, hash_feed_token(&token))
         .await
     {
-        Ok(event) => match event {
-            Some(event) => event,
-            _ => unreachable!(),
-        },
+        Ok(_) => HttpResponse::Ok().json(FeedTokenResPayload::new(
+            "Calendar feed token created, any previous token is revoked.".to_string(),
+            token,
+            &feed_location(&req),
+        )),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while creating the calendar feed token!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+// The public feed is mounted in the same scope as the token route
+fn feed_location(req: &HttpRequest) -> String {
+    format!("{}/calendar/feed.ics", scope_prefix(req, "/calendar/token"))
+}
+
+async fn calendar_response(db: &MongoDB, owner: ObjectId) -> HttpResponse {
+    let calendar = match db.export_booking_rows(vec![owner], None, None).await {
+        Ok(cursor) => render_calendar(cursor).await,
+        Err(err) => Err(err),
+    };
+
+    match calendar {
+        Ok(calendar) => HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .body(calendar),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while rendering the calendar feed!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+// Imports CSV rows of "eventId, day, amount", either all-or-nothing or best-effort
+#[utoipa::path(
+    tag = "bookings",
+    params(ImportPayload),
+    request_body(content = String, content_type = "text/csv", description = "Rows of eventId, day, amount"),
+    responses(
+        (status = 200, description = "Import report", body = ReportResPayload<ImportReport>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 409, description = "An event's bookings changed during an atomic import, nothing was saved", body = ErrorResPayload),
+        (status = 422, description = "Invalid CSV, or rejected rows in atomic mode", body = ReportResPayload<ImportReport>),
+    )
+)]
+#[post("/import")]
+pub async fn import_bookings(
+    db: Data<MongoDB>,
+    rules: Data<BookingRules>,
+    query: Query<ImportPayload>,
+    body: Bytes,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    let atomic = query.atomic.unwrap_or(false);
+
+    let rows = match parse_rows(&body) {
+        Ok(rows) if !rows.is_empty() => rows,
+        Ok(_) => {
+            return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+                "An error occurred!".to_string(),
+                "No rows to import. Required columns: eventId, day, amount".to_string(),
+            ))
+        }
+        Err(err) => {
+            return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+                "An error occurred while parsing the CSV!".to_string(),
+                err.to_string(),
+            ))
+        }
+    };
+
+    match import_rows(&db, &rules, &user_id, rows, atomic).await {
+        Ok(report) if atomic && !report.applied => HttpResponse::UnprocessableEntity().json(
+            ReportResPayload::new("Import rejected, nothing was saved.".to_string(), report),
+        ),
+        Ok(report) => HttpResponse::Ok().json(ReportResPayload::new(
+            "Import completed.".to_string(),
+            report,
+        )),
+        Err(err) => booking_error_response(err),
+    }
+}
+
+#[utoipa::path(
+    tag = "bookings",
+    params(NotificationsPayload),
+    responses(
+        (status = 200, description = "Server-sent events stream of the booking changes, named after their kind", body = BookingNotification, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 422, description = "Invalid payload", body = ErrorResPayload),
+    )
+)]
+#[get("/notifications")]
+pub async fn booking_notifications(
+    db: Data<MongoDB>,
+    query: Query<NotificationsPayload>,
+    user_oid: ReqData<UserObjectId>,
+) -> HttpResponse {
+    if !query.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid event IDs or days. At least one event ID or day is required. Required date format: YYYY-MM-DD".to_string(),
+        ));
+    }
+
+    let UserObjectId(user_oid) = user_oid.into_inner();
+    let filter = query.filter(user_oid);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(sse_stream(db.subscribe_notifications(), filter))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    description = "Registers a webhook for the events of the caller's own bookings. There are no global subscriptions across users.",
+    request_body = NewWebhookPayload,
+    responses(
+        (status = 201, description = "Webhook registered, the signing secret is only returned once", body = WebhookResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 422, description = "Invalid URL or events, or the URL resolves to an internal address", body = ErrorResPayload),
+    )
+)]
+#[post("/webhooks")]
+pub async fn create_webhook(
+    req: HttpRequest,
+    db: Data<MongoDB>,
+    config: Data<Config>,
+    payload: Json<NewWebhookPayload>,
+    user_oid: ReqData<UserObjectId>,
+) -> HttpResponse {
+    if !payload.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid URL or events. URL must use http or https. Events must be any of: booking.created, booking.deleted, event.fully_booked".to_string(),
+        ));
+    }
+    if let Err(err) = check_webhook_url(&payload.url, config.webhook_allow_private_targets).await {
+        return HttpResponse::UnprocessableEntity()
+            .json(ErrorResPayload::new("An error occurred!".to_string(), err));
+    }
+    let UserObjectId(user_oid) = user_oid.into_inner();
+    let NewWebhookPayload { url, mut events } = payload.into_inner();
+    events.sort();
+    events.dedup();
+
+    let secret = new_webhook_secret();
+    let webhook = Webhook {
+        id: ObjectId::new(),
+        owner: user_oid,
+        url,
+        events,
+        secret: secret.clone(),
+        active: true,
+        createdAt: DateTime::now(),
+    };
+
+    match db.insert_webhook(&webhook).await {
+        Ok(_) => HttpResponse::Created()
+            .insert_header((header::LOCATION, webhook_location(&req, webhook.id)))
+            .json(WebhookResPayload {
+                message: "Webhook registered.".to_string(),
+                webhook: webhook.into(),
+                secret: Some(secret),
+            }),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while registering the webhook!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The user's webhooks", body = WebhooksResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+    )
+)]
+#[get("/webhooks")]
+pub async fn list_webhooks(db: Data<MongoDB>, user_id: ReqData<UserId>) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+
+    match db.find_webhooks(&user_id).await {
+        Ok(webhooks) => HttpResponse::Ok().json(WebhooksResPayload {
+            message: "Webhooks fetched.".to_string(),
+            webhooks: webhooks.into_iter().map(WebhookInfo::from).collect(),
+        }),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while fetching the webhooks!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("webhookId" = ObjectIdSchema, Path, description = "ID of the webhook")),
+    responses(
+        (status = 204, description = "Webhook removed, its pending deliveries are dropped"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Webhook not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid webhook ID", body = ErrorResPayload),
+    )
+)]
+#[delete("/webhooks/{webhookId}")]
+pub async fn delete_webhook(
+    db: Data<MongoDB>,
+    webhook_id: Path<ObjectIdPath>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(webhook_id) = webhook_id.into_inner();
+
+    match db.delete_webhook(&user_id, webhook_id).await {
+        Ok(result) if result.deleted_count == 0 => {
+            HttpResponse::NotFound().json(ErrorResPayload::new(
+                "An error occurred!".to_string(),
+                "Webhook not found".to_string(),
+            ))
+        }
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while removing the webhook!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("webhookId" = ObjectIdSchema, Path, description = "ID of the webhook"), DeliveriesPayload),
+    responses(
+        (status = 200, description = "Delivery log of the webhook, latest first", body = WebhookDeliveriesResPayload),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 404, description = "Webhook not found", body = ErrorResPayload),
+        (status = 422, description = "Invalid webhook ID or payload", body = ErrorResPayload),
+    )
+)]
+#[get("/webhooks/{webhookId}/deliveries")]
+pub async fn webhook_deliveries(
+    db: Data<MongoDB>,
+    webhook_id: Path<ObjectIdPath>,
+    query: Query<DeliveriesPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    if !query.validate() {
+        return HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid limit. Limit must be between 1 and 500".to_string(),
+        ));
+    }
+    let UserId(user_id) = user_id.into_inner();
+    let ObjectIdPath(webhook_id) = webhook_id.into_inner();
+
+    match db.find_owned_webhook(&user_id, webhook_id).await {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResPayload::new(
+                "An error occurred!".to_string(),
+                "Webhook not found".to_string(),
+            ))
+        }
         Err(err) => {
             return HttpResponse::InternalServerError().json(ErrorResPayload::new(
-                "An error ocurred!".to_string(),
+                "An error occurred while fetching the webhook!".to_string(),
                 err.to_string(),
             ))
         }
     };
 
-    // Check if the Event and Booking Detail belong to the same destination Day and if there are multiple Booking Details for the destination Day
-    // Removes the event from the destination Day
-    if !compare(event.date, &booking_detail.toDate) && !has_more_details {
-        match db
-            .remove_event_from_day(&user_id, &booking_detail.toDate, event.id)
-            .await
-        {
-            Ok(_) => (),
-            Err(err) => {
-                return HttpResponse::InternalServerError().json(ErrorResPayload::new(
-                    "An error ocurred".to_string(),
-                    err.to_string(),
-                ))
-            }
-        };
+    match db
+        .find_webhook_deliveries(&user_id, webhook_id, query.status, query.limit())
+        .await
+    {
+        Ok(deliveries) => HttpResponse::Ok().json(WebhookDeliveriesResPayload {
+            message: "Webhook deliveries fetched.".to_string(),
+            deliveries,
+        }),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while fetching the webhook deliveries!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    params(ConsistencyPayload),
+    responses(
+        (status = 200, description = "Inconsistencies between the days and events, repaired with fix", body = ReportResPayload<ConsistencyReport>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResPayload),
+        (status = 403, description = "User is not an admin", body = ErrorResPayload),
+    )
+)]
+#[post("/admin/consistency")]
+pub async fn consistency_check(
+    db: Data<MongoDB>,
+    config: Data<Config>,
+    query: Query<ConsistencyPayload>,
+    user_id: ReqData<UserId>,
+) -> HttpResponse {
+    let UserId(user_id) = user_id.into_inner();
+    if !config.admin_user_ids.contains(&user_id) {
+        return HttpResponse::Forbidden().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Only admins can check the consistency".to_string(),
+        ));
     }
 
-    HttpResponse::Ok().json(EventResPayload::new(
-        "Booking detail deleted!".to_string(),
-        Some(updated_event),
-    ))
+    let fix = query.fix.unwrap_or(false);
+    match check_consistency(&db, fix).await {
+        Ok(report) => {
+            let message = if report.inconsistencies.is_empty() {
+                "No inconsistencies found."
+            } else if fix {
+                "Inconsistencies found and repaired where possible."
+            } else {
+                "Inconsistencies found."
+            };
+            HttpResponse::Ok().json(ReportResPayload::new(message.to_string(), report))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResPayload::new(
+            "An error occurred while checking the consistency!".to_string(),
+            err.to_string(),
+        )),
+    }
+}
+
+fn webhook_location(req: &HttpRequest, webhook_id: ObjectId) -> String {
+    format!(
+        "{}/webhooks/{}",
+        scope_prefix(req, "/webhooks"),
+        webhook_id.to_hex()
+    )
 }
@@ -0,0 +1,80 @@
+use actix_web::{
+    error::InternalError,
+    web::{self, JsonConfig, PathConfig, ServiceConfig},
+    HttpResponse,
+};
+
+use super::graphql::graphql;
+use super::routes::{
+    book_event, booking_notifications, booking_report, calendar_feed, consistency_check,
+    create_booking, create_feed_token, create_webhook, delete_booking, delete_event,
+    delete_webhook, distribute_event, export_bookings, get_booking, health, import_bookings,
+    list_bookings, list_webhooks, public_calendar_feed, update_booking, webhook_deliveries,
+};
+use super::routes_structs::ErrorResPayload;
+use crate::middlewares::auth::CheckLoginFactory;
+
+// Public routes have to be registered before the authenticated scope
+pub fn routes(cfg: &mut ServiceConfig) {
+    cfg.app_data(path_config())
+        .app_data(json_config())
+        .service(public_calendar_feed)
+        .service(
+            web::scope("")
+                .wrap(CheckLoginFactory)
+                .service(health)
+                .service(book_event)
+                .service(delete_event)
+                .service(create_booking)
+                .service(list_bookings)
+                .service(get_booking)
+                .service(update_booking)
+                .service(delete_booking)
+                .service(distribute_event)
+                .service(booking_report)
+                .service(export_bookings)
+                .service(calendar_feed)
+                .service(create_feed_token)
+                .service(import_bookings)
+                .service(booking_notifications)
+                .service(create_webhook)
+                .service(list_webhooks)
+                .service(delete_webhook)
+                .service(webhook_deliveries)
+                .service(consistency_check)
+                .service(graphql),
+        );
+}
+
+// The routes that existed before /api/v1, served unversioned for the already deployed clients.
+// Newer routes are only served under /api/v1.
+pub fn legacy_routes(cfg: &mut ServiceConfig) {
+    cfg.app_data(path_config()).app_data(json_config()).service(
+        web::scope("")
+            .wrap(CheckLoginFactory)
+            .service(health)
+            .service(book_event)
+            .service(delete_event),
+    );
+}
+
+// Path segments that fail to parse, e.g. malformed ObjectIds, are reported like invalid query payloads
+fn path_config() -> PathConfig {
+    PathConfig::default().error_handler(|err, _req| {
+        let response = HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            "Invalid ID format!".to_string(),
+        ));
+        InternalError::from_response(err, response).into()
+    })
+}
+
+fn json_config() -> JsonConfig {
+    JsonConfig::default().error_handler(|err, _req| {
+        let response = HttpResponse::UnprocessableEntity().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            err.to_string(),
+        ));
+        InternalError::from_response(err, response).into()
+    })
+}
@@ -1,16 +1,75 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use actix_web::{http::header, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate};
+use sha2::{Digest, Sha256};
 
-pub fn compare(timestamp: f64, datestring: &String) -> bool {
-    let event_date = NaiveDateTime::from_timestamp_opt(timestamp as i64 / 1000, 0)
+use super::routes_structs::ErrorResPayload;
+use crate::services::booking::BookingError;
+
+pub fn format_event_date(timestamp: f64) -> String {
+    DateTime::from_timestamp(timestamp as i64 / 1000, 0)
         .expect("Invalid Event date format")
         .format("%Y-%m-%d")
-        .to_string();
+        .to_string()
+}
+
+pub fn compare(timestamp: f64, datestring: &str) -> bool {
+    let event_date = format_event_date(timestamp);
 
-    let booking_detail_date = NaiveDate::parse_from_str(&datestring, "%Y-%m-%d")
+    let booking_detail_date = NaiveDate::parse_from_str(datestring, "%Y-%m-%d")
         .expect("Invalid Booking Detail date format")
-        .and_hms(0, 0, 0)
         .format("%Y-%m-%d")
         .to_string();
 
     event_date == booking_detail_date
 }
+
+// Compares digests of the tokens, so the comparison time doesn't depend on the matching prefix
+pub fn bearer_token_matches(req: &HttpRequest, expected: &str) -> bool {
+    let token = match req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(token) => token,
+        None => return false,
+    };
+    Sha256::digest(token.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+pub fn booking_error_response(err: BookingError) -> HttpResponse {
+    match err {
+        BookingError::EventNotFound => HttpResponse::NotFound().json(ErrorResPayload::new(
+            "An error occurred!".to_string(),
+            err.to_string(),
+        )),
+        BookingError::BookingNotFound => HttpResponse::NotFound().json(ErrorResPayload::new(
+            "An error ocurred!".to_string(),
+            err.to_string(),
+        )),
+        BookingError::RuleViolation(_) => {
+            HttpResponse::UnprocessableEntity().json(ErrorResPayload::with_code(
+                "An error occurred!".to_string(),
+                err.to_string(),
+                err.code(),
+            ))
+        }
+        BookingError::CapacityExceeded { .. } => HttpResponse::BadRequest().json(
+            ErrorResPayload::new("An error occurred!".to_string(), err.to_string()),
+        ),
+        BookingError::DailyCapExceeded { .. } => {
+            HttpResponse::BadRequest().json(ErrorResPayload::with_code(
+                "An error occurred!".to_string(),
+                err.to_string(),
+                err.code(),
+            ))
+        }
+        BookingError::Conflict => HttpResponse::Conflict().json(ErrorResPayload::with_code(
+            "An error occurred!".to_string(),
+            err.to_string(),
+            err.code(),
+        )),
+        BookingError::Database(message, err) => HttpResponse::InternalServerError()
+            .json(ErrorResPayload::new(message.to_string(), err.to_string())),
+    }
+}
@@ -1,12 +1,60 @@
 use chrono::NaiveDate;
-use serde::{Deserialize, Serialize};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize)]
+use crate::models::mongo::{BookingDetail, DeliveryStatus, Webhook, WebhookDelivery};
+use crate::models::schema::{DateTimeSchema, ObjectIdSchema};
+use crate::services::booking::DayChange;
+use crate::services::distribution::DistributionStrategy;
+use crate::services::notifications::NotificationFilter;
+use crate::services::webhooks::WebhookEvent;
+
+// Upper bound for the number of days a single distribution may span
+const MAX_DISTRIBUTION_DAYS: i64 = 62;
+
+const DEFAULT_DELIVERIES_LIMIT: i64 = 50;
+const MAX_DELIVERIES_LIMIT: i64 = 500;
+
+#[derive(Serialize, ToSchema)]
 pub struct Health<'a> {
     pub status: &'a str,
 }
 
-#[derive(Serialize)]
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub gitSha: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            gitSha: env!("GIT_SHA"),
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct DependencyStatus {
+    pub name: &'static str,
+    /// "up" or "down"
+    pub status: &'static str,
+    pub latencyMs: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResPayload {
+    /// "ok" or "degraded"
+    pub status: &'static str,
+    pub dependencies: Vec<DependencyStatus>,
+    pub build: BuildInfo,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct EventResPayload<T> {
     pub message: String,
     pub event: Option<T>,
@@ -18,33 +66,207 @@ impl<T> EventResPayload<T> {
     }
 }
 
-#[derive(Serialize)]
+// Booking and deletion routes return the would-be changes in dry-run mode
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum BookingResPayload<T> {
+    Completed(EventResPayload<T>),
+    DryRun(DryRunResPayload<T>),
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct DryRunResPayload<T> {
+    pub message: String,
+    pub dryRun: bool,
+    pub availableHours: f32,
+    pub lastForDay: bool,
+    pub dayChanges: Vec<DayChange>,
+    pub event: Option<T>,
+}
+
+impl<T> DryRunResPayload<T> {
+    pub fn new(
+        message: String,
+        available_hours: f32,
+        last_for_day: bool,
+        day_changes: Vec<DayChange>,
+        event: Option<T>,
+    ) -> Self {
+        Self {
+            message,
+            dryRun: true,
+            availableHours: available_hours,
+            lastForDay: last_for_day,
+            dayChanges: day_changes,
+            event,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct BookingDetailsResPayload {
+    pub message: String,
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+    pub bookingDetails: Vec<BookingDetail>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct BookingDetailResPayload {
+    pub message: String,
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+    pub bookingDetail: BookingDetail,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct WebhookInfo {
+    #[schema(value_type = ObjectIdSchema)]
+    pub id: ObjectId,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    #[schema(value_type = DateTimeSchema)]
+    pub createdAt: DateTime,
+}
+
+impl From<Webhook> for WebhookInfo {
+    fn from(webhook: Webhook) -> Self {
+        WebhookInfo {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            active: webhook.active,
+            createdAt: webhook.createdAt,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookResPayload {
+    pub message: String,
+    pub webhook: WebhookInfo,
+    /// Signing secret, only returned when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhooksResPayload {
+    pub message: String,
+    pub webhooks: Vec<WebhookInfo>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookDeliveriesResPayload {
+    pub message: String,
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, ToSchema)]
+pub struct DistributionResPayload<T> {
+    pub message: String,
+    pub committed: bool,
+    pub bookingDetails: Vec<BookingDetail>,
+    pub event: Option<T>,
+}
+
+impl<T> DistributionResPayload<T> {
+    pub fn new(
+        message: String,
+        committed: bool,
+        booking_details: Vec<BookingDetail>,
+        event: Option<T>,
+    ) -> Self {
+        Self {
+            message,
+            committed,
+            bookingDetails: booking_details,
+            event,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReportResPayload<T> {
+    pub message: String,
+    pub report: T,
+}
+
+impl<T> ReportResPayload<T> {
+    pub fn new(message: String, report: T) -> Self {
+        Self { message, report }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FeedTokenResPayload {
+    pub message: String,
+    pub token: String,
+    pub url: String,
+}
+
+impl FeedTokenResPayload {
+    // feed_path is the path of the public feed route, see routes::feed_location
+    pub fn new(message: String, token: String, feed_path: &str) -> Self {
+        let url = format!("{feed_path}?token={token}");
+        Self {
+            message,
+            token,
+            url,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct ErrorResPayload {
     pub message: String,
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 impl ErrorResPayload {
     pub fn new(message: String, error: String) -> Self {
-        Self { message, error }
+        Self {
+            message,
+            error,
+            code: None,
+        }
+    }
+
+    pub fn with_code(message: String, error: String, code: &str) -> Self {
+        Self {
+            message,
+            error,
+            code: Some(code.to_string()),
+        }
     }
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct BookingPayload {
+    /// ID of the event to book hours from
     pub eventId: String,
+    /// Day to book the hours onto, in YYYY-MM-DD format
     pub day: String,
+    /// Amount of hours, at least 0.25
     pub amount: String,
+    /// Validate the booking and return the would-be changes without saving them
+    pub dryRun: Option<bool>,
 }
 
 impl BookingPayload {
     pub fn validate(&self) -> bool {
-        let event_id_ok = self.eventId.len() == 24;
-        let day_format_ok = match NaiveDate::parse_from_str(&self.day, "%Y-%m-%d") {
-            Ok(_) => true,
-            Err(_) => false,
-        };
+        let event_id_ok = ObjectId::parse_str(&self.eventId).is_ok();
+        let day_format_ok = NaiveDate::parse_from_str(&self.day, "%Y-%m-%d").is_ok();
         // let day_format_ok = self.day.split("-").collect::<Vec<&str>>().len() == 3;
         let amount_ok = self.amount.parse::<f32>().unwrap_or_default() >= 0.25;
         if !event_id_ok || !day_format_ok || !amount_ok {
@@ -55,13 +277,288 @@ impl BookingPayload {
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DeleteBookingPayload {
+    /// ID of the booking detail to delete
     pub bookingId: String,
+    /// Validate the deletion and return the would-be changes without saving them
+    pub dryRun: Option<bool>,
 }
 
 impl DeleteBookingPayload {
     pub fn validate(&self) -> bool {
-        self.bookingId.len() == 24
+        ObjectId::parse_str(&self.bookingId).is_ok()
     }
 }
+
+// ObjectId path segment, malformed IDs are rejected by the path extractor, see routes_config
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectIdPath(pub ObjectId);
+
+impl<'de> Deserialize<'de> for ObjectIdPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        ObjectId::parse_str(&id)
+            .map(ObjectIdPath)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct NewBookingPayload {
+    /// Day to book the hours onto, in YYYY-MM-DD format
+    pub day: String,
+    /// Amount of hours, at least 0.25
+    pub amount: f32,
+}
+
+impl NewBookingPayload {
+    pub fn validate(&self) -> bool {
+        let day_format_ok = NaiveDate::parse_from_str(&self.day, "%Y-%m-%d").is_ok();
+        let amount_ok = self.amount >= 0.25;
+        day_format_ok && amount_ok
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UpdateBookingPayload {
+    /// Day to move the booked hours to, in YYYY-MM-DD format
+    pub day: Option<String>,
+    /// New amount of hours, at least 0.25
+    pub amount: Option<f32>,
+}
+
+impl UpdateBookingPayload {
+    pub fn validate(&self) -> bool {
+        let day_format_ok = match &self.day {
+            Some(day) => NaiveDate::parse_from_str(day, "%Y-%m-%d").is_ok(),
+            None => true,
+        };
+        let amount_ok = match self.amount {
+            Some(amount) => amount >= 0.25,
+            None => true,
+        };
+        let changes_ok = self.day.is_some() || self.amount.is_some();
+        day_format_ok && amount_ok && changes_ok
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct NewWebhookPayload {
+    /// HTTP(S) URL the signed payloads are posted to
+    pub url: String,
+    /// Any of: booking.created, booking.deleted, event.fully_booked
+    pub events: Vec<String>,
+}
+
+impl NewWebhookPayload {
+    pub fn validate(&self) -> bool {
+        let url_ok = match reqwest::Url::parse(&self.url) {
+            Ok(url) => url.scheme() == "http" || url.scheme() == "https",
+            Err(_) => false,
+        };
+        let events_ok = !self.events.is_empty()
+            && self
+                .events
+                .iter()
+                .all(|event| event.parse::<WebhookEvent>().is_ok());
+        url_ok && events_ok
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesPayload {
+    /// One of: pending, delivered, failed
+    pub status: Option<DeliveryStatus>,
+    /// Number of deliveries to return, latest first. Defaults to 50, at most 500
+    pub limit: Option<i64>,
+}
+
+impl DeliveriesPayload {
+    pub fn validate(&self) -> bool {
+        match self.limit {
+            Some(limit) => (1..=MAX_DELIVERIES_LIMIT).contains(&limit),
+            None => true,
+        }
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_DELIVERIES_LIMIT)
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedTokenPayload {
+    /// Secret calendar feed token
+    pub token: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DryRunPayload {
+    /// Validate the request and return the would-be changes without saving them
+    pub dryRun: Option<bool>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationsPayload {
+    /// Comma separated IDs of the events to receive booking changes for
+    pub eventIds: Option<String>,
+    /// Comma separated booking days to receive booking changes for, in YYYY-MM-DD format
+    pub days: Option<String>,
+}
+
+impl NotificationsPayload {
+    pub fn validate(&self) -> bool {
+        let event_ids_ok = split_list(&self.eventIds)
+            .iter()
+            .all(|event_id| ObjectId::parse_str(event_id).is_ok());
+        let days_ok = split_list(&self.days)
+            .iter()
+            .all(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").is_ok());
+        let subscription_ok =
+            !split_list(&self.eventIds).is_empty() || !split_list(&self.days).is_empty();
+        event_ids_ok && days_ok && subscription_ok
+    }
+
+    pub fn filter(&self, owner: ObjectId) -> NotificationFilter {
+        NotificationFilter {
+            owner,
+            event_ids: split_list(&self.eventIds)
+                .iter()
+                .filter_map(|event_id| ObjectId::parse_str(event_id).ok())
+                .collect(),
+            days: split_list(&self.days),
+        }
+    }
+}
+
+fn split_list(list: &Option<String>) -> Vec<String> {
+    match list {
+        Some(list) => list
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DistributionPayload {
+    /// ID of the event whose remaining hours are distributed
+    pub eventId: String,
+    /// First day of the range, in YYYY-MM-DD format
+    pub from: String,
+    /// Last day of the range, in YYYY-MM-DD format
+    pub to: String,
+    /// One of: even, fillToDailyCap, weekdays. Defaults to even
+    pub strategy: Option<String>,
+    /// Save the distribution instead of returning a preview
+    pub commit: Option<bool>,
+}
+
+// The validated date range and strategy of a DistributionPayload
+pub struct DistributionRequest {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub strategy: DistributionStrategy,
+}
+
+impl DistributionPayload {
+    // Returns the parsed range and strategy, or None if the payload is invalid
+    pub fn validate(&self) -> Option<DistributionRequest> {
+        if ObjectId::parse_str(&self.eventId).is_err() {
+            return None;
+        }
+        let (from, to) = match (
+            NaiveDate::parse_from_str(&self.from, "%Y-%m-%d"),
+            NaiveDate::parse_from_str(&self.to, "%Y-%m-%d"),
+        ) {
+            (Ok(from), Ok(to)) if from <= to && (to - from).num_days() < MAX_DISTRIBUTION_DAYS => {
+                (from, to)
+            }
+            _ => return None,
+        };
+        let strategy = match &self.strategy {
+            Some(strategy) => strategy.parse::<DistributionStrategy>().ok()?,
+            None => DistributionStrategy::Even,
+        };
+        Some(DistributionRequest { from, to, strategy })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportPayload {
+    /// First day of the range, in YYYY-MM-DD format
+    pub from: Option<String>,
+    /// Last day of the range, in YYYY-MM-DD format
+    pub to: Option<String>,
+}
+
+impl ReportPayload {
+    pub fn validate(&self) -> bool {
+        let parse = |day: &Option<String>| match day {
+            Some(day) => NaiveDate::parse_from_str(day, "%Y-%m-%d").map(Some),
+            None => Ok(None),
+        };
+        match (parse(&self.from), parse(&self.to)) {
+            (Ok(Some(from)), Ok(Some(to))) => from <= to,
+            (Ok(_), Ok(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportPayload {
+    /// First day of the range, in YYYY-MM-DD format
+    pub from: Option<String>,
+    /// Last day of the range, in YYYY-MM-DD format
+    pub to: Option<String>,
+    /// One of: csv, xlsx. Defaults to csv
+    pub format: Option<String>,
+    /// Export the bookings of a team managed by the user
+    pub teamId: Option<String>,
+}
+
+impl ExportPayload {
+    pub fn validate(&self) -> bool {
+        let range_ok = ReportPayload {
+            from: self.from.clone(),
+            to: self.to.clone(),
+        }
+        .validate();
+        let format_ok = matches!(self.format.as_deref(), None | Some("csv") | Some("xlsx"));
+        let team_id_ok = match &self.teamId {
+            Some(team_id) => ObjectId::parse_str(team_id).is_ok(),
+            None => true,
+        };
+        range_ok && format_ok && team_id_ok
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConsistencyPayload {
+    /// Repair the inconsistencies that can be repaired
+    pub fix: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportPayload {
+    /// Only save the rows if every row is accepted
+    pub atomic: Option<bool>,
+}
@@ -0,0 +1,336 @@
+use booking_machine::api::routes_helpers::format_event_date;
+use booking_machine::api::routes_structs::BookingPayload;
+use booking_machine::config::{AdminConfig, ConnectionArgs};
+use booking_machine::handlers::mongo::MongoDB;
+use booking_machine::models::mongo::{Day, EventDocument};
+use booking_machine::services::booking::{
+    apply_booking, apply_deletion, plan_booking, plan_deletion, BookingError,
+};
+use booking_machine::services::consistency::{check_booking_state, Inconsistency};
+use booking_machine::services::metrics::Metrics;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use tracing_subscriber::EnvFilter;
+
+// Support tasks on the bookings, run against the same database and booking rules as the server
+#[derive(Parser, Debug)]
+#[command(
+    name = "booking-admin",
+    version,
+    about = "Admin CLI of the Booking Machine"
+)]
+struct AdminCli {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table, global = true)]
+    format: OutputFormat,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// Shows an event along with its bookings
+    Event {
+        #[arg(value_parser = object_id)]
+        event_id: String,
+    },
+    /// Books hours of an event on behalf of a user, following the booking rules
+    Book {
+        /// The owner of the event's day
+        #[arg(long, value_parser = object_id)]
+        user: String,
+        #[arg(long, value_parser = object_id)]
+        event: String,
+        /// Day to book the hours onto, in YYYY-MM-DD format
+        #[arg(long)]
+        day: String,
+        /// Amount of hours, at least 0.25
+        #[arg(long)]
+        amount: String,
+        /// Show the would-be event without saving the booking
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Deletes a booking on behalf of its user
+    Unbook {
+        /// The owner of the booked event's day
+        #[arg(long, value_parser = object_id)]
+        user: String,
+        #[arg(long, value_parser = object_id)]
+        booking: String,
+        /// Show the would-be event without deleting the booking
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Lists a user's days in a date range
+    Days {
+        #[arg(long, value_parser = object_id)]
+        user: String,
+        /// First day, in YYYY-MM-DD format
+        #[arg(long, value_parser = date)]
+        from: String,
+        /// Last day, in YYYY-MM-DD format
+        #[arg(long, value_parser = date)]
+        to: String,
+    },
+    /// Recalculates durationBooked and booked from the bookings of an event
+    Recalculate {
+        #[arg(value_parser = object_id, required_unless_present = "all")]
+        event_id: Option<String>,
+        /// Recalculate every event
+        #[arg(long, conflicts_with = "event_id")]
+        all: bool,
+    },
+}
+
+fn object_id(value: &str) -> Result<String, String> {
+    match ObjectId::parse_str(value) {
+        Ok(_) => Ok(value.to_string()),
+        Err(_) => Err("not a valid ObjectId".to_string()),
+    }
+}
+
+fn date(value: &str) -> Result<String, String> {
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(_) => Ok(value.to_string()),
+        Err(_) => Err("required date format: YYYY-MM-DD".to_string()),
+    }
+}
+
+#[actix_web::main]
+async fn main() {
+    let cli = AdminCli::parse();
+    let config = match AdminConfig::load(&cli.connection) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    // Logs go to stderr, so they don't mix with the output
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::new(&config.log.filter))
+        .init();
+
+    let mongo = match MongoDB::init(&config.mongo, Metrics::new()).await {
+        Ok(mongo) => mongo,
+        Err(err) => {
+            eprintln!("Failed to connect to Mongo DB: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let result = run(&cli, &config, &mongo).await;
+    mongo.shutdown().await;
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: &AdminCli, config: &AdminConfig, mongo: &MongoDB) -> Result<(), String> {
+    let format = cli.format;
+    match &cli.command {
+        AdminCommand::Event { event_id } => match mongo.find_event_by_id(event_id).await {
+            Ok(Some(event)) => print_event(format, &event),
+            Ok(None) => Err(BookingError::EventNotFound.to_string()),
+            Err(err) => Err(format!("An error occurred while fetching the event! {err}")),
+        },
+        AdminCommand::Book {
+            user,
+            event,
+            day,
+            amount,
+            dry_run,
+        } => {
+            // Same checks as the /book route
+            let payload = BookingPayload {
+                eventId: event.clone(),
+                day: day.clone(),
+                amount: amount.clone(),
+                dryRun: Some(*dry_run),
+            };
+            if !payload.validate() {
+                return Err("Invalid date format or amount. Required date format: YYYY-MM-DD. Amount must be at least 0.25h".to_string());
+            }
+            let amount: f32 = amount.parse().unwrap_or_default();
+            match mongo.find_event_by_id(event).await {
+                Ok(Some(event)) => check_owner(mongo, user, &event).await?,
+                Ok(None) => return Err(BookingError::EventNotFound.to_string()),
+                Err(err) => {
+                    return Err(format!("An error occurred while fetching the event! {err}"))
+                }
+            }
+
+            let plan = plan_booking(mongo, &config.booking_rules, user, event, day, amount)
+                .await
+                .map_err(booking_error)?;
+            if *dry_run {
+                return print_event(format, &plan.event);
+            }
+            mongo.check_transactions().await?;
+            match apply_booking(mongo, user, plan).await {
+                Ok(Some(event)) => print_event(format, &event),
+                Ok(None) => Err(BookingError::EventNotFound.to_string()),
+                Err(err) => Err(booking_error(err)),
+            }
+        }
+        AdminCommand::Unbook {
+            user,
+            booking,
+            dry_run,
+        } => {
+            match mongo.find_bookingdetail_by_id(booking).await {
+                Ok(Some(event)) => check_owner(mongo, user, &event).await?,
+                Ok(None) => return Err(BookingError::BookingNotFound.to_string()),
+                Err(err) => {
+                    return Err(format!(
+                        "An error occurred while fetching the booking! {err}"
+                    ))
+                }
+            }
+
+            let plan = plan_deletion(mongo, user, booking)
+                .await
+                .map_err(booking_error)?;
+            if *dry_run {
+                return print_event(format, &plan.event);
+            }
+            mongo.check_transactions().await?;
+            let event = apply_deletion(mongo, user, plan)
+                .await
+                .map_err(booking_error)?;
+            print_event(format, &event)
+        }
+        AdminCommand::Days { user, from, to } => match mongo.find_days(user, from, to).await {
+            Ok(days) => print_days(format, &days),
+            Err(err) => Err(format!("An error occurred while fetching the days! {err}")),
+        },
+        AdminCommand::Recalculate { event_id, all } => {
+            let mut changes: Vec<Inconsistency> = vec![];
+            if *all {
+                let mut events = mongo
+                    .find_all_events()
+                    .await
+                    .map_err(|err| format!("An error occurred while fetching the events! {err}"))?;
+                while let Some(event) = events
+                    .try_next()
+                    .await
+                    .map_err(|err| format!("An error occurred while fetching the events! {err}"))?
+                {
+                    changes.extend(check_booking_state(mongo, &event, true).await.map_err(
+                        |err| format!("An error occurred while updating the event! {err}"),
+                    )?);
+                }
+            } else if let Some(event_id) = event_id {
+                let event = match mongo.find_event_by_id(event_id).await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => return Err(BookingError::EventNotFound.to_string()),
+                    Err(err) => {
+                        return Err(format!("An error occurred while fetching the event! {err}"))
+                    }
+                };
+                changes = check_booking_state(mongo, &event, true)
+                    .await
+                    .map_err(|err| format!("An error occurred while updating the event! {err}"))?;
+            }
+            print_changes(format, &changes)
+        }
+    }
+}
+
+// The booking logic reports the events of other users as not found, the owner is named here instead
+async fn check_owner(mongo: &MongoDB, user: &str, event: &EventDocument) -> Result<(), String> {
+    match mongo.find_day_by_id(event.day).await {
+        Ok(Some(day)) if day.owner.to_hex() == user => Ok(()),
+        Ok(Some(day)) => Err(format!(
+            "The event {} belongs to the user {}, not {user}",
+            event.id, day.owner
+        )),
+        Ok(None) => Err(format!("The day {} of the event doesn't exist", event.day)),
+        Err(err) => Err(format!("An error occurred while fetching the day! {err}")),
+    }
+}
+
+fn booking_error(err: BookingError) -> String {
+    format!("{}: {err}", err.code())
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    println!("{json}");
+    Ok(())
+}
+
+fn print_event(format: OutputFormat, event: &EventDocument) -> Result<(), String> {
+    if let OutputFormat::Json = format {
+        return print_json(event);
+    }
+
+    println!("Event          {} ({})", event.title, event.id);
+    println!("Date           {}", format_event_date(event.date));
+    println!("Duration       {}h", event.duration);
+    println!("Booked         {}h", event.durationBooked.unwrap_or(0.0));
+    println!("Fully booked   {}", if event.booked { "yes" } else { "no" });
+    println!();
+    println!("{:<26} {:<12} {:>7}", "BOOKING", "DAY", "AMOUNT");
+    for booking_detail in event.bookingDetails.iter().flatten() {
+        println!(
+            "{:<26} {:<12} {:>6}h",
+            booking_detail.id.to_hex(),
+            booking_detail.toDate,
+            booking_detail.amount
+        );
+    }
+    Ok(())
+}
+
+fn print_days(format: OutputFormat, days: &[Day]) -> Result<(), String> {
+    if let OutputFormat::Json = format {
+        return print_json(&days);
+    }
+
+    println!("{:<12} {:<26} {:>6}", "DAY", "ID", "EVENTS");
+    for day in days {
+        println!(
+            "{:<12} {:<26} {:>6}",
+            day.day,
+            day.id.to_hex(),
+            day.events.len()
+        );
+    }
+    Ok(())
+}
+
+fn print_changes(format: OutputFormat, changes: &[Inconsistency]) -> Result<(), String> {
+    if let OutputFormat::Json = format {
+        return print_json(&changes);
+    }
+
+    if changes.is_empty() {
+        println!("Nothing to recalculate");
+        return Ok(());
+    }
+    println!("{:<26} {:<18} DETAIL", "EVENT", "KIND");
+    for change in changes {
+        println!(
+            "{:<26} {:<18} {}",
+            change.eventId.to_hex(),
+            change.kind.as_str(),
+            change.detail
+        );
+    }
+    Ok(())
+}
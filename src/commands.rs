@@ -0,0 +1,112 @@
+use crate::config::Command;
+use crate::handlers::bootstrap::{ensure_schema, DriftKind};
+use crate::handlers::migrations::run_migrations;
+use crate::handlers::mongo::MongoDB;
+use crate::services::consistency::check_consistency;
+
+// Runs a maintenance subcommand instead of the server, returns the process exit code
+pub async fn run_command(command: &Command, mongo: &MongoDB) -> i32 {
+    match command {
+        Command::Schema { check } => schema(mongo, *check).await,
+        Command::Migrate {
+            dry_run,
+            batch_size,
+        } => migrate(mongo, *dry_run, *batch_size).await,
+        Command::Consistency { fix } => consistency(mongo, *fix).await,
+    }
+}
+
+async fn schema(mongo: &MongoDB, check: bool) -> i32 {
+    let drift = match ensure_schema(mongo.database(), !check).await {
+        Ok(drift) => drift,
+        Err(err) => {
+            eprintln!("Schema bootstrap failed: {err}");
+            return 1;
+        }
+    };
+
+    if drift.is_empty() {
+        println!("The indexes and validators are up to date");
+        return 0;
+    }
+    for item in &drift {
+        println!(
+            "{:<8} {:<20} {:<26} {}",
+            item.collection,
+            item.kind.as_str(),
+            item.target,
+            if item.fixed { "fixed" } else { "not fixed" }
+        );
+    }
+
+    // Unexpected indexes are never dropped, so they don't fail the check
+    let unfixed = drift
+        .iter()
+        .any(|item| !item.fixed && item.kind != DriftKind::UnexpectedIndex);
+    if unfixed {
+        1
+    } else {
+        0
+    }
+}
+
+async fn migrate(mongo: &MongoDB, dry_run: bool, batch_size: u32) -> i32 {
+    let outcomes = match run_migrations(mongo.database(), dry_run, batch_size).await {
+        Ok(outcomes) => outcomes,
+        Err(err) => {
+            eprintln!("Migration failed: {err}");
+            return 1;
+        }
+    };
+
+    for outcome in &outcomes {
+        let status = if outcome.already_applied {
+            "already applied".to_string()
+        } else if dry_run {
+            format!("would update up to {} documents", outcome.matched)
+        } else {
+            format!(
+                "updated {} of {} documents",
+                outcome.modified, outcome.matched
+            )
+        };
+        println!(
+            "{:>4} {:<28} {:<8} {}",
+            outcome.version, outcome.name, outcome.collection, status
+        );
+    }
+    0
+}
+
+async fn consistency(mongo: &MongoDB, fix: bool) -> i32 {
+    let report = match check_consistency(mongo, fix).await {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("Consistency check failed: {err}");
+            return 1;
+        }
+    };
+
+    for inconsistency in &report.inconsistencies {
+        println!(
+            "{:<24} event {} day {:<10} {}{}",
+            inconsistency.kind.as_str(),
+            inconsistency.eventId,
+            inconsistency.day.as_deref().unwrap_or("-"),
+            inconsistency.detail,
+            if inconsistency.fixed { " (fixed)" } else { "" }
+        );
+    }
+    println!(
+        "Scanned {} days and {} events, found {} inconsistencies",
+        report.daysScanned,
+        report.eventsScanned,
+        report.inconsistencies.len()
+    );
+
+    if report.has_unfixed() {
+        1
+    } else {
+        0
+    }
+}
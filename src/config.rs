@@ -0,0 +1,1126 @@
+use std::{collections::HashMap, fmt, fs, str::FromStr, time::Duration};
+
+use actix_web::http::{header::HeaderName, Method};
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::services::booking_rules::BookingRules;
+use crate::services::holidays::load_holidays;
+
+const DEFAULT_PORT: u16 = 8081;
+const DEFAULT_DB_NAME: &str = "project-manager";
+const DEFAULT_MONGO_CONNECT_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_MONGO_SERVER_SELECTION_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_MONGO_STARTUP_RETRIES: u32 = 10;
+const DEFAULT_MONGO_STARTUP_BACKOFF_MS: u64 = 500;
+const DEFAULT_MONGO_STARTUP_MAX_BACKOFF_MS: u64 = 30_000;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_OUTBOX_SINKS: &str = "webhooks";
+const DEFAULT_CORS_METHODS: [&str; 4] = ["GET", "POST", "PATCH", "DELETE"];
+const DEFAULT_CORS_HEADERS: [&str; 4] = ["Authorization", "Accept", "Content-Type", "X-Request-Id"];
+const DEFAULT_CORS_EXPOSE_HEADERS: [&str; 4] = ["Location", "Link", "Deprecation", "X-Request-Id"];
+const DEFAULT_CORS_MAX_AGE: usize = 3600;
+const DEFAULT_LOG_FILTER: &str = "info";
+
+// Command line flags take precedence over the environment, which takes precedence over the config file
+#[derive(Parser, Debug, Default)]
+#[command(
+    name = "booking-machine",
+    version,
+    about = "The Booking Machine server"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    /// Port to listen on, defaults to PORT or 8081
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Allowed CORS origin, can be repeated. Defaults to CORS_ORIGINS or ORIGIN
+    #[arg(long = "origin")]
+    pub origins: Vec<String>,
+    /// Runs a maintenance task instead of the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// The flags shared by the server and the admin CLI
+#[derive(Args, Debug, Default)]
+pub struct ConnectionArgs {
+    /// Path to a TOML config file, defaults to CONFIG_FILE
+    #[arg(long, short, global = true)]
+    pub config: Option<String>,
+    /// "development" or "production", defaults to ENV or development
+    #[arg(long, global = true)]
+    pub env: Option<String>,
+    /// Mongo connection string, defaults to MONGO_URI_DEV in development and MONGO_URI otherwise
+    #[arg(long, global = true)]
+    pub mongo_uri: Option<String>,
+    /// Mongo database name, defaults to MONGO_DB_NAME or project-manager
+    #[arg(long, global = true)]
+    pub db_name: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Creates the indexes and validators of the days, events, teams and outbox collections and reports drift
+    Schema {
+        /// Only report the drift, without changing anything
+        #[arg(long)]
+        check: bool,
+    },
+    /// Applies the pending data migrations of the days and events collections
+    Migrate {
+        /// Only count the documents each pending migration would update
+        #[arg(long)]
+        dry_run: bool,
+        /// Documents updated per round trip
+        #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..))]
+        batch_size: u32,
+    },
+    /// Reports the days and events that don't agree with each other
+    Consistency {
+        /// Repair the inconsistencies that can be repaired
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    Development,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Development => "development",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl FromStr for Environment {
+    type Err = ();
+
+    fn from_str(env: &str) -> Result<Self, Self::Err> {
+        match env {
+            "development" => Ok(Environment::Development),
+            "production" => Ok(Environment::Production),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupMode {
+    // Exit right away when the database can't be reached
+    FailFast,
+    // Retry with exponential backoff, e.g. while the database container is starting
+    Wait,
+}
+
+impl FromStr for StartupMode {
+    type Err = ();
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "fail-fast" => Ok(StartupMode::FailFast),
+            "wait" => Ok(StartupMode::Wait),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaBootstrap {
+    Off,
+    // Only log the indexes and validators that differ from the declared ones, the default.
+    // The schema subcommand applies them.
+    Check,
+    // Create the missing indexes and validators at startup, see handlers::bootstrap
+    Apply,
+}
+
+impl FromStr for SchemaBootstrap {
+    type Err = ();
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(SchemaBootstrap::Off),
+            "check" => Ok(SchemaBootstrap::Check),
+            "apply" => Ok(SchemaBootstrap::Apply),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct MongoConfig {
+    pub uri: String,
+    pub db_name: String,
+    pub min_pool_size: Option<u32>,
+    pub max_pool_size: Option<u32>,
+    pub connect_timeout: Duration,
+    pub server_selection_timeout: Duration,
+    pub startup_mode: StartupMode,
+    // Connection attempts after the first one in wait mode, 0 retries forever
+    pub startup_retries: u32,
+    pub startup_backoff: Duration,
+    pub startup_max_backoff: Duration,
+    pub schema_bootstrap: SchemaBootstrap,
+}
+
+pub struct CorsConfig {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct LogConfig {
+    pub filter: String,
+    pub format: LogFormat,
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboxSinkConfig {
+    Log,
+    Http { url: String },
+    File { path: String },
+    Webhooks,
+}
+
+impl OutboxSinkConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutboxSinkConfig::Log => "log",
+            OutboxSinkConfig::Http { .. } => "http",
+            OutboxSinkConfig::File { .. } => "file",
+            OutboxSinkConfig::Webhooks => "webhooks",
+        }
+    }
+}
+
+// The server configuration, loaded and validated once at startup and shared through Data<Config>
+pub struct Config {
+    pub port: u16,
+    pub env: Environment,
+    pub cors: CorsConfig,
+    pub user_auth: String,
+    // Users allowed to call the /admin routes
+    pub admin_user_ids: Vec<String>,
+    // Bearer token required by /metrics, which is disabled while it isn't set
+    pub metrics_token: Option<String>,
+    // Lets webhooks target loopback and private addresses, off unless set for local testing
+    pub webhook_allow_private_targets: bool,
+    pub mongo: MongoConfig,
+    // How long in-flight requests may take to finish when the server stops
+    pub shutdown_timeout: u64,
+    pub booking_rules: BookingRules,
+    pub outbox_sinks: Vec<OutboxSinkConfig>,
+    pub log: LogConfig,
+}
+
+// Every problem found while loading the configuration, so they can be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    port: Option<u16>,
+    env: Option<String>,
+    user_auth: Option<String>,
+    admin_user_ids: Option<Vec<String>>,
+    metrics_token: Option<String>,
+    webhook_allow_private_targets: Option<bool>,
+    cors: CorsFileConfig,
+    shutdown_timeout_secs: Option<u64>,
+    mongo: MongoFileConfig,
+    booking: BookingFileConfig,
+    outbox: OutboxFileConfig,
+    log: LogFileConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct CorsFileConfig {
+    origins: Option<Vec<String>>,
+    methods: Option<Vec<String>>,
+    headers: Option<Vec<String>>,
+    expose_headers: Option<Vec<String>>,
+    credentials: Option<bool>,
+    max_age: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct MongoFileConfig {
+    uri: Option<String>,
+    db_name: Option<String>,
+    min_pool_size: Option<u32>,
+    max_pool_size: Option<u32>,
+    connect_timeout_ms: Option<u64>,
+    server_selection_timeout_ms: Option<u64>,
+    startup_mode: Option<String>,
+    startup_retries: Option<u32>,
+    startup_backoff_ms: Option<u64>,
+    startup_max_backoff_ms: Option<u64>,
+    schema_bootstrap: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BookingFileConfig {
+    max_days_before: Option<i64>,
+    max_days_after: Option<i64>,
+    allow_future: Option<bool>,
+    block_weekends: Option<bool>,
+    holidays_file: Option<String>,
+    daily_cap: Option<f32>,
+    daily_cap_overrides: Option<HashMap<String, f32>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct OutboxFileConfig {
+    sinks: Option<Vec<String>>,
+    http_url: Option<String>,
+    file_path: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LogFileConfig {
+    filter: Option<String>,
+    format: Option<String>,
+    otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        Self::load_with(cli, &Vars::Process)
+    }
+
+    fn load_with(cli: &Cli, vars: &Vars) -> Result<Config, ConfigError> {
+        let mut errors = vec![];
+        let (file, env) = load_file_and_env(&cli.connection, vars, &mut errors)?;
+
+        let port = cli
+            .port
+            .or(vars.value("PORT", &mut errors))
+            .or(file.port)
+            .unwrap_or(DEFAULT_PORT);
+        if port == 0 {
+            errors.push("PORT must be between 1 and 65535".to_string());
+        }
+
+        let cors = cors(cli, vars, file.cors, &mut errors);
+
+        let user_auth = required(
+            vars.string("USER_AUTH").or(file.user_auth),
+            "USER_AUTH",
+            &mut errors,
+        );
+
+        let admin_user_ids = vars
+            .list("ADMIN_USER_IDS")
+            .or(file.admin_user_ids)
+            .unwrap_or_default();
+        for admin_user_id in &admin_user_ids {
+            if ObjectId::parse_str(admin_user_id).is_err() {
+                errors.push(format!(
+                    "ADMIN_USER_IDS: \"{admin_user_id}\" is not a valid ObjectId"
+                ));
+            }
+        }
+
+        let metrics_token = vars.string("METRICS_TOKEN").or(file.metrics_token);
+        if metrics_token.as_ref().is_some_and(|token| token.len() < 16) {
+            errors.push("METRICS_TOKEN must be at least 16 characters long".to_string());
+        }
+
+        let webhook_allow_private_targets = vars
+            .value("WEBHOOK_ALLOW_PRIVATE_TARGETS", &mut errors)
+            .or(file.webhook_allow_private_targets)
+            .unwrap_or(false);
+
+        let mongo = mongo(&cli.connection, vars, env, file.mongo, &mut errors);
+
+        let shutdown_timeout = vars
+            .value("SHUTDOWN_TIMEOUT_SECS", &mut errors)
+            .or(file.shutdown_timeout_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS);
+
+        let booking_rules = booking_rules(vars, file.booking, &mut errors);
+        let outbox_sinks = outbox_sinks(vars, file.outbox, &mut errors);
+        let log = log(vars, file.log, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(ConfigError(errors));
+        }
+
+        Ok(Config {
+            port,
+            env,
+            cors,
+            user_auth,
+            admin_user_ids,
+            metrics_token,
+            webhook_allow_private_targets,
+            mongo,
+            shutdown_timeout,
+            booking_rules,
+            outbox_sinks,
+            log,
+        })
+    }
+}
+
+// The subset of the configuration the admin CLI needs, see src/bin/booking-admin.rs
+pub struct AdminConfig {
+    pub mongo: MongoConfig,
+    pub booking_rules: BookingRules,
+    pub log: LogConfig,
+}
+
+impl AdminConfig {
+    pub fn load(args: &ConnectionArgs) -> Result<AdminConfig, ConfigError> {
+        let mut errors = vec![];
+        let vars = Vars::Process;
+        let (file, env) = load_file_and_env(args, &vars, &mut errors)?;
+
+        let mongo = mongo(args, &vars, env, file.mongo, &mut errors);
+        let booking_rules = booking_rules(&vars, file.booking, &mut errors);
+        let log = log(&vars, file.log, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(ConfigError(errors));
+        }
+
+        Ok(AdminConfig {
+            mongo,
+            booking_rules,
+            log,
+        })
+    }
+}
+
+// The environment is resolved first, as the .env file is only read in development
+fn load_file_and_env(
+    args: &ConnectionArgs,
+    vars: &Vars,
+    errors: &mut Vec<String>,
+) -> Result<(FileConfig, Environment), ConfigError> {
+    let file = match args.config.clone().or_else(|| vars.string("CONFIG_FILE")) {
+        Some(path) => read_config_file(&path).map_err(|err| ConfigError(vec![err]))?,
+        None => FileConfig::default(),
+    };
+
+    let env_name = args
+        .env
+        .clone()
+        .or_else(|| vars.string("ENV"))
+        .or(file.env.clone())
+        .unwrap_or(Environment::Development.as_str().to_string());
+    let env = match env_name.parse::<Environment>() {
+        Ok(env) => env,
+        Err(_) => {
+            errors.push(format!(
+                "ENV must be \"development\" or \"production\", got \"{env_name}\""
+            ));
+            Environment::Development
+        }
+    };
+
+    if env == Environment::Development {
+        vars.load_dotenv();
+    }
+
+    Ok((file, env))
+}
+
+fn read_config_file(path: &str) -> Result<FileConfig, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Unable to read the config file {path}: {err}"))?;
+    toml::from_str(&content).map_err(|err| format!("Invalid config file {path}: {err}"))
+}
+
+fn mongo(
+    args: &ConnectionArgs,
+    vars: &Vars,
+    env: Environment,
+    file: MongoFileConfig,
+    errors: &mut Vec<String>,
+) -> MongoConfig {
+    let mongo_uri_var = match env {
+        Environment::Development => "MONGO_URI_DEV",
+        Environment::Production => "MONGO_URI",
+    };
+    let mongo_uri = required(
+        args.mongo_uri
+            .clone()
+            .or_else(|| vars.string(mongo_uri_var))
+            .or(file.uri),
+        mongo_uri_var,
+        errors,
+    );
+    if !mongo_uri.is_empty()
+        && !mongo_uri.starts_with("mongodb://")
+        && !mongo_uri.starts_with("mongodb+srv://")
+    {
+        errors.push(format!(
+            "{mongo_uri_var} must start with mongodb:// or mongodb+srv://"
+        ));
+    }
+
+    let db_name = args
+        .db_name
+        .clone()
+        .or_else(|| vars.string("MONGO_DB_NAME"))
+        .or(file.db_name)
+        .unwrap_or(DEFAULT_DB_NAME.to_string());
+    if db_name.is_empty() || db_name.len() > 63 || db_name.contains(['/', '\\', '.', ' ', '"', '$'])
+    {
+        errors.push(format!(
+            "MONGO_DB_NAME \"{db_name}\" is not a valid Mongo database name"
+        ));
+    }
+
+    let min_pool_size = vars
+        .value("MONGO_MIN_POOL_SIZE", errors)
+        .or(file.min_pool_size);
+    let max_pool_size = vars
+        .value("MONGO_MAX_POOL_SIZE", errors)
+        .or(file.max_pool_size);
+    if max_pool_size == Some(0) {
+        errors.push("MONGO_MAX_POOL_SIZE must be greater than 0".to_string());
+    }
+    if let (Some(min), Some(max)) = (min_pool_size, max_pool_size) {
+        if min > max {
+            errors
+                .push("MONGO_MIN_POOL_SIZE cannot be greater than MONGO_MAX_POOL_SIZE".to_string());
+        }
+    }
+
+    let connect_timeout = millis(
+        vars,
+        "MONGO_CONNECT_TIMEOUT_MS",
+        file.connect_timeout_ms,
+        DEFAULT_MONGO_CONNECT_TIMEOUT_MS,
+        errors,
+    );
+    let server_selection_timeout = millis(
+        vars,
+        "MONGO_SERVER_SELECTION_TIMEOUT_MS",
+        file.server_selection_timeout_ms,
+        DEFAULT_MONGO_SERVER_SELECTION_TIMEOUT_MS,
+        errors,
+    );
+
+    let startup_mode_name = vars
+        .string("MONGO_STARTUP_MODE")
+        .or(file.startup_mode)
+        .unwrap_or("wait".to_string());
+    let startup_mode = match startup_mode_name.parse::<StartupMode>() {
+        Ok(startup_mode) => startup_mode,
+        Err(_) => {
+            errors.push(format!(
+                "MONGO_STARTUP_MODE must be \"wait\" or \"fail-fast\", got \"{startup_mode_name}\""
+            ));
+            StartupMode::Wait
+        }
+    };
+    let startup_retries = vars
+        .value("MONGO_STARTUP_RETRIES", errors)
+        .or(file.startup_retries)
+        .unwrap_or(DEFAULT_MONGO_STARTUP_RETRIES);
+    let startup_backoff = millis(
+        vars,
+        "MONGO_STARTUP_BACKOFF_MS",
+        file.startup_backoff_ms,
+        DEFAULT_MONGO_STARTUP_BACKOFF_MS,
+        errors,
+    );
+    let startup_max_backoff = millis(
+        vars,
+        "MONGO_STARTUP_MAX_BACKOFF_MS",
+        file.startup_max_backoff_ms,
+        DEFAULT_MONGO_STARTUP_MAX_BACKOFF_MS,
+        errors,
+    );
+    if startup_backoff > startup_max_backoff {
+        errors.push(
+            "MONGO_STARTUP_BACKOFF_MS cannot be greater than MONGO_STARTUP_MAX_BACKOFF_MS"
+                .to_string(),
+        );
+    }
+
+    let schema_bootstrap_name = vars
+        .string("MONGO_SCHEMA_BOOTSTRAP")
+        .or(file.schema_bootstrap)
+        .unwrap_or("check".to_string());
+    let schema_bootstrap = match schema_bootstrap_name.parse::<SchemaBootstrap>() {
+        Ok(schema_bootstrap) => schema_bootstrap,
+        Err(_) => {
+            errors.push(format!(
+                "MONGO_SCHEMA_BOOTSTRAP must be \"apply\", \"check\" or \"off\", got \"{schema_bootstrap_name}\""
+            ));
+            SchemaBootstrap::Check
+        }
+    };
+
+    MongoConfig {
+        uri: mongo_uri,
+        db_name,
+        min_pool_size,
+        max_pool_size,
+        connect_timeout,
+        server_selection_timeout,
+        startup_mode,
+        startup_retries,
+        startup_backoff,
+        startup_max_backoff,
+        schema_bootstrap,
+    }
+}
+
+// A positive duration in milliseconds
+fn millis(
+    vars: &Vars,
+    name: &str,
+    file: Option<u64>,
+    default: u64,
+    errors: &mut Vec<String>,
+) -> Duration {
+    let millis = vars.value(name, errors).or(file).unwrap_or(default);
+    if millis == 0 {
+        errors.push(format!("{name} must be greater than 0"));
+    }
+    Duration::from_millis(millis)
+}
+
+fn cors(cli: &Cli, vars: &Vars, file: CorsFileConfig, errors: &mut Vec<String>) -> CorsConfig {
+    let origins = if cli.origins.is_empty() {
+        vars.list("CORS_ORIGINS")
+            .or_else(|| vars.list("ORIGIN"))
+            .or(file.origins)
+            .unwrap_or_default()
+    } else {
+        cli.origins.clone()
+    };
+    if origins.is_empty() {
+        errors.push("CORS_ORIGINS (or ORIGIN) is required".to_string());
+    }
+    for origin in &origins {
+        if !is_valid_origin(origin) {
+            errors.push(format!(
+                "CORS origin \"{origin}\" must be \"*\" or look like https://app.example.com or https://*.example.com"
+            ));
+        }
+    }
+
+    let methods = vars
+        .list("CORS_METHODS")
+        .or(file.methods)
+        .unwrap_or(DEFAULT_CORS_METHODS.map(String::from).to_vec());
+    for method in &methods {
+        if Method::from_bytes(method.as_bytes()).is_err() {
+            errors.push(format!("Invalid CORS method \"{method}\""));
+        }
+    }
+
+    let headers = vars
+        .list("CORS_HEADERS")
+        .or(file.headers)
+        .unwrap_or(DEFAULT_CORS_HEADERS.map(String::from).to_vec());
+    let expose_headers = vars
+        .list("CORS_EXPOSE_HEADERS")
+        .or(file.expose_headers)
+        .unwrap_or(DEFAULT_CORS_EXPOSE_HEADERS.map(String::from).to_vec());
+    for header in headers.iter().chain(&expose_headers) {
+        if HeaderName::from_bytes(header.as_bytes()).is_err() {
+            errors.push(format!("Invalid CORS header \"{header}\""));
+        }
+    }
+
+    let credentials = vars
+        .value("CORS_CREDENTIALS", errors)
+        .or(file.credentials)
+        .unwrap_or(false);
+    if credentials && origins.iter().any(|origin| origin == "*") {
+        errors.push("CORS_CREDENTIALS cannot be used with the \"*\" origin".to_string());
+    }
+
+    // 0 disables the Access-Control-Max-Age header
+    let max_age = match vars
+        .value("CORS_MAX_AGE", errors)
+        .or(file.max_age)
+        .unwrap_or(DEFAULT_CORS_MAX_AGE)
+    {
+        0 => None,
+        max_age => Some(max_age),
+    };
+
+    CorsConfig {
+        origins,
+        methods,
+        headers,
+        expose_headers,
+        credentials,
+        max_age,
+    }
+}
+
+// "*", "scheme://host[:port]" or "scheme://*.host[:port]"
+fn is_valid_origin(origin: &str) -> bool {
+    if origin == "*" {
+        return true;
+    }
+    let (scheme, host) = match origin.split_once("://") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let host = host.strip_prefix("*.").unwrap_or(host);
+    match reqwest::Url::parse(&format!("{scheme}://{host}")) {
+        Ok(url) => {
+            url.has_host()
+                && !host.contains(['*', '/', '?', '#'])
+                && url.path() == "/"
+                && url.username().is_empty()
+        }
+        Err(_) => false,
+    }
+}
+
+fn booking_rules(vars: &Vars, file: BookingFileConfig, errors: &mut Vec<String>) -> BookingRules {
+    let max_days_before = vars
+        .value("BOOKING_MAX_DAYS_BEFORE", errors)
+        .or(file.max_days_before);
+    let max_days_after = vars
+        .value("BOOKING_MAX_DAYS_AFTER", errors)
+        .or(file.max_days_after);
+    for (name, max_days) in [
+        ("BOOKING_MAX_DAYS_BEFORE", max_days_before),
+        ("BOOKING_MAX_DAYS_AFTER", max_days_after),
+    ] {
+        if max_days.is_some_and(|max_days| max_days < 0) {
+            errors.push(format!("{name} cannot be negative"));
+        }
+    }
+
+    let holidays = match vars.string("BOOKING_HOLIDAYS_FILE").or(file.holidays_file) {
+        Some(path) => load_holidays(&path).unwrap_or_else(|err| {
+            errors.push(format!("BOOKING_HOLIDAYS_FILE: {err}"));
+            Default::default()
+        }),
+        None => Default::default(),
+    };
+
+    let daily_cap = vars.value("BOOKING_DAILY_CAP", errors).or(file.daily_cap);
+    let daily_cap_overrides = match vars.string("BOOKING_DAILY_CAP_OVERRIDES") {
+        Some(overrides) => parse_cap_overrides(&overrides).unwrap_or_else(|err| {
+            errors.push(format!("BOOKING_DAILY_CAP_OVERRIDES: {err}"));
+            HashMap::new()
+        }),
+        None => file.daily_cap_overrides.unwrap_or_default(),
+    };
+    for user_id in daily_cap_overrides.keys() {
+        if ObjectId::parse_str(user_id).is_err() {
+            errors.push(format!(
+                "BOOKING_DAILY_CAP_OVERRIDES: \"{user_id}\" is not a valid ObjectId"
+            ));
+        }
+    }
+    if daily_cap.is_some_and(|cap| cap <= 0.0)
+        || daily_cap_overrides.values().any(|cap| *cap <= 0.0)
+    {
+        errors.push("Daily caps must be greater than 0".to_string());
+    }
+
+    BookingRules {
+        max_days_before,
+        max_days_after,
+        allow_future: vars
+            .value("BOOKING_ALLOW_FUTURE", errors)
+            .or(file.allow_future)
+            .unwrap_or(true),
+        block_weekends: vars
+            .value("BOOKING_BLOCK_WEEKENDS", errors)
+            .or(file.block_weekends)
+            .unwrap_or(false),
+        holidays,
+        daily_cap,
+        daily_cap_overrides,
+    }
+}
+
+// Parses "userId:hours,userId:hours" into a map of per-user daily caps
+fn parse_cap_overrides(value: &str) -> Result<HashMap<String, f32>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((user_id, cap)) => match cap.trim().parse::<f32>() {
+                Ok(cap) => Ok((user_id.trim().to_string(), cap)),
+                Err(_) => Err(format!("Invalid daily cap for user {user_id}: {cap}")),
+            },
+            None => Err(format!("Invalid daily cap override: {entry}")),
+        })
+        .collect()
+}
+
+fn outbox_sinks(
+    vars: &Vars,
+    file: OutboxFileConfig,
+    errors: &mut Vec<String>,
+) -> Vec<OutboxSinkConfig> {
+    let names = match vars.string("OUTBOX_SINKS") {
+        Some(names) => names
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect(),
+        None => file.sinks.unwrap_or(vec![DEFAULT_OUTBOX_SINKS.to_string()]),
+    };
+    let http_url = vars.string("OUTBOX_HTTP_URL").or(file.http_url);
+    let file_path = vars.string("OUTBOX_FILE_PATH").or(file.file_path);
+
+    let mut sinks: Vec<OutboxSinkConfig> = vec![];
+    for name in names.iter().filter(|name| !name.is_empty()) {
+        let sink = match name.as_str() {
+            "log" => OutboxSinkConfig::Log,
+            "http" => match &http_url {
+                Some(url) => match reqwest::Url::parse(url) {
+                    Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+                        OutboxSinkConfig::Http { url: url.clone() }
+                    }
+                    _ => {
+                        errors.push(format!("OUTBOX_HTTP_URL \"{url}\" is not an http(s) URL"));
+                        continue;
+                    }
+                },
+                None => {
+                    errors.push("OUTBOX_HTTP_URL is required for the http outbox sink".to_string());
+                    continue;
+                }
+            },
+            "file" => match &file_path {
+                Some(path) => OutboxSinkConfig::File { path: path.clone() },
+                None => {
+                    errors
+                        .push("OUTBOX_FILE_PATH is required for the file outbox sink".to_string());
+                    continue;
+                }
+            },
+            "webhooks" => OutboxSinkConfig::Webhooks,
+            _ => {
+                errors.push(format!(
+                    "Unknown outbox sink \"{name}\", expected log, http, file or webhooks"
+                ));
+                continue;
+            }
+        };
+        if sinks.iter().any(|existing| existing.name() == sink.name()) {
+            errors.push(format!("Outbox sink \"{name}\" is listed twice"));
+            continue;
+        }
+        sinks.push(sink);
+    }
+    sinks
+}
+
+fn log(vars: &Vars, file: LogFileConfig, errors: &mut Vec<String>) -> LogConfig {
+    let filter = vars
+        .string("RUST_LOG")
+        .or(file.filter)
+        .unwrap_or(DEFAULT_LOG_FILTER.to_string());
+    if let Err(err) = EnvFilter::try_new(&filter) {
+        errors.push(format!("Invalid RUST_LOG filter \"{filter}\": {err}"));
+    }
+
+    let format_name = vars
+        .string("LOG_FORMAT")
+        .or(file.format)
+        .unwrap_or("json".to_string());
+    let format = match format_name.parse::<LogFormat>() {
+        Ok(format) => format,
+        Err(_) => {
+            errors.push(format!(
+                "LOG_FORMAT must be \"json\" or \"pretty\", got \"{format_name}\""
+            ));
+            LogFormat::Json
+        }
+    };
+
+    let otlp_endpoint = vars
+        .string("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+        .or(file.otlp_endpoint);
+    if let Some(endpoint) = &otlp_endpoint {
+        if !matches!(reqwest::Url::parse(endpoint), Ok(url) if matches!(url.scheme(), "http" | "https"))
+        {
+            errors.push(format!(
+                "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT \"{endpoint}\" is not an http(s) URL"
+            ));
+        }
+    }
+
+    LogConfig {
+        filter,
+        format,
+        otlp_endpoint,
+    }
+}
+
+// Where the variables are read from, tests use a map instead of the shared process environment
+enum Vars {
+    Process,
+    #[cfg(test)]
+    Map(HashMap<String, String>),
+}
+
+impl Vars {
+    // Empty variables count as unset, as in sample.env
+    fn string(&self, name: &str) -> Option<String> {
+        let value = match self {
+            Vars::Process => std::env::var(name).ok(),
+            #[cfg(test)]
+            Vars::Map(vars) => vars.get(name).cloned(),
+        };
+        value.filter(|value| !value.is_empty())
+    }
+
+    fn value<T: FromStr>(&self, name: &str, errors: &mut Vec<String>) -> Option<T> {
+        let value = self.string(name)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                errors.push(format!("Invalid value for {name}: {value}"));
+                None
+            }
+        }
+    }
+
+    // Comma separated values, ignoring blanks
+    fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.string(name).map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+
+    // The .env file only feeds the process environment
+    fn load_dotenv(&self) {
+        if matches!(self, Vars::Process) {
+            dotenv().ok();
+        }
+    }
+}
+
+fn required(value: Option<String>, name: &str, errors: &mut Vec<String>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value,
+        _ => {
+            errors.push(format!("{name} is required"));
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_origins() {
+        for origin in [
+            "*",
+            "http://localhost:3000",
+            "https://app.example.com",
+            "https://*.example.com",
+            "https://*.example.com:8443",
+        ] {
+            assert!(is_valid_origin(origin), "{origin} should be valid");
+        }
+    }
+
+    #[test]
+    fn invalid_origins() {
+        for origin in [
+            "",
+            "example.com",
+            "https://",
+            "https://app.example.com/",
+            "https://app.example.com/path",
+            "https://app.example.com?query",
+            "https://user@app.example.com",
+            "https://*example.com",
+            "https://app.*.example.com",
+            "https://*.*.example.com",
+        ] {
+            assert!(!is_valid_origin(origin), "{origin} should be invalid");
+        }
+    }
+
+    #[test]
+    fn cap_overrides_are_parsed() {
+        let overrides =
+            parse_cap_overrides(" 634e1f1c1f1c1f1c1f1c1f1c:6, 634e1f1c1f1c1f1c1f1c1f1d : 7.5 ,")
+                .unwrap();
+        assert_eq!(
+            overrides,
+            HashMap::from([
+                ("634e1f1c1f1c1f1c1f1c1f1c".to_string(), 6.0),
+                ("634e1f1c1f1c1f1c1f1c1f1d".to_string(), 7.5),
+            ])
+        );
+        assert_eq!(parse_cap_overrides("").unwrap(), HashMap::new());
+    }
+
+    #[test]
+    fn invalid_cap_overrides_are_rejected() {
+        assert!(parse_cap_overrides("634e1f1c1f1c1f1c1f1c1f1c").is_err());
+        assert!(parse_cap_overrides("634e1f1c1f1c1f1c1f1c1f1c:eight").is_err());
+        assert!(parse_cap_overrides("634e1f1c1f1c1f1c1f1c1f1c:6,other").is_err());
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vars {
+        Vars::Map(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_environment_and_the_file() {
+        let path = std::env::temp_dir().join(format!(
+            "booking-machine-config-{}.toml",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            r#"
+port = 9000
+user_auth = "file-secret"
+
+[cors]
+origins = ["https://file.example.com"]
+
+[mongo]
+uri = "mongodb://file:27017"
+db_name = "file-db"
+"#,
+        )
+        .unwrap();
+
+        let mut cli = Cli {
+            port: Some(9200),
+            connection: ConnectionArgs {
+                config: Some(path.to_string_lossy().to_string()),
+                mongo_uri: Some("mongodb://flag:27017".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let env = vars(&[("PORT", "9100"), ("MONGO_DB_NAME", "env-db")]);
+
+        let config = Config::load_with(&cli, &env).unwrap();
+        assert_eq!(config.port, 9200);
+        assert_eq!(config.mongo.uri, "mongodb://flag:27017");
+        assert_eq!(config.mongo.db_name, "env-db");
+        assert_eq!(config.user_auth, "file-secret");
+        assert_eq!(config.cors.origins, vec!["https://file.example.com"]);
+        assert_eq!(config.env, Environment::Development);
+        assert!(!config.webhook_allow_private_targets);
+
+        cli.port = None;
+        cli.connection.mongo_uri = None;
+        let config = Config::load_with(&cli, &env).unwrap();
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.mongo.uri, "mongodb://file:27017");
+
+        let config = Config::load_with(&cli, &vars(&[])).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.mongo.db_name, "file-db");
+
+        // Empty variables count as unset
+        let config = Config::load_with(&cli, &vars(&[("PORT", "")])).unwrap();
+        assert_eq!(config.port, 9000);
+
+        // Every invalid value is reported at once
+        let env = vars(&[("PORT", "not-a-port"), ("CORS_ORIGINS", "app.example.com")]);
+        let errors = match Config::load_with(&cli, &env) {
+            Ok(_) => panic!("Invalid values should be rejected"),
+            Err(ConfigError(errors)) => errors,
+        };
+        assert_eq!(errors.len(), 2, "{errors:?}");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cap_overrides_must_be_keyed_by_user_ids() {
+        let mut errors = vec![];
+        let env = vars(&[(
+            "BOOKING_DAILY_CAP_OVERRIDES",
+            "634e1f1c1f1c1f1c1f1c1f1c:6,alice:7",
+        )]);
+        let rules = booking_rules(&env, BookingFileConfig::default(), &mut errors);
+        assert_eq!(
+            errors,
+            vec!["BOOKING_DAILY_CAP_OVERRIDES: \"alice\" is not a valid ObjectId"]
+        );
+        assert_eq!(rules.daily_cap_overrides.len(), 2);
+
+        // Overrides from the config file are checked the same way
+        let mut errors = vec![];
+        let file = BookingFileConfig {
+            daily_cap_overrides: Some(HashMap::from([("bob".to_string(), 6.0)])),
+            ..Default::default()
+        };
+        booking_rules(&vars(&[]), file, &mut errors);
+        assert_eq!(
+            errors,
+            vec!["BOOKING_DAILY_CAP_OVERRIDES: \"bob\" is not a valid ObjectId"]
+        );
+    }
+}
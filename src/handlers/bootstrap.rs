@@ -0,0 +1,371 @@
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    options::{CreateCollectionOptions, IndexOptions, ValidationAction, ValidationLevel},
+    Database, IndexModel,
+};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::SchemaBootstrap;
+
+const DATE_PATTERN: &str = "^[0-9]{4}-[0-9]{2}-[0-9]{2}$";
+
+// Relayed outbox entries are kept around for a week to look into deliveries
+const OUTBOX_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// An index the queries of handlers::mongo rely on
+struct IndexSpec {
+    collection: &'static str,
+    name: &'static str,
+    keys: Document,
+    // Makes it a TTL index, expiring the documents once the date field is older
+    expire_after: Option<Duration>,
+}
+
+// A collection along with the JSON schema its documents are validated against
+struct CollectionSpec {
+    name: &'static str,
+    validator: Document,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DriftKind {
+    MissingCollection,
+    MissingIndex,
+    IndexDiffers,
+    ValidatorDiffers,
+    // Indexes that aren't declared here are reported, but never dropped
+    UnexpectedIndex,
+}
+
+impl DriftKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DriftKind::MissingCollection => "missing collection",
+            DriftKind::MissingIndex => "missing index",
+            DriftKind::IndexDiffers => "index differs",
+            DriftKind::ValidatorDiffers => "validator differs",
+            DriftKind::UnexpectedIndex => "unexpected index",
+        }
+    }
+}
+
+// A difference between the database and the declared indexes and validators
+#[derive(Debug, Serialize)]
+pub struct SchemaDrift {
+    pub collection: &'static str,
+    pub target: String,
+    pub kind: DriftKind,
+    pub fixed: bool,
+}
+
+fn required_indexes() -> Vec<IndexSpec> {
+    vec![
+        // find_days, add_event_to_day, remove_event_from_day
+        IndexSpec {
+            collection: "days",
+            name: "owner_1_day_1",
+            keys: doc! {"owner": 1, "day": 1},
+            expire_after: None,
+        },
+        // find_bookingdetail_by_id
+        IndexSpec {
+            collection: "events",
+            name: "bookingDetails._id_1",
+            keys: doc! {"bookingDetails._id": 1},
+            expire_after: None,
+        },
+        // find_booked_amount_for_day, booking_report, export_booking_rows
+        IndexSpec {
+            collection: "events",
+            name: "bookingDetails.toDate_1",
+            keys: doc! {"bookingDetails.toDate": 1},
+            expire_after: None,
+        },
+        // booking_report's partially booked events
+        IndexSpec {
+            collection: "events",
+            name: "date_1",
+            keys: doc! {"date": 1},
+            expire_after: None,
+        },
+        // Prunes the relayed outbox entries, pending ones have no relayedAt
+        IndexSpec {
+            collection: "outbox",
+            name: "relayedAt_1",
+            keys: doc! {"relayedAt": 1},
+            expire_after: Some(OUTBOX_RETENTION),
+        },
+    ]
+}
+
+// Mirrors models::mongo::Day, EventDocument and Team. Fields the models don't know are allowed,
+// as the documents are shared with the project manager.
+fn collections() -> Vec<CollectionSpec> {
+    vec![
+        CollectionSpec {
+            name: "days",
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["owner", "day", "events", "updatedAt"],
+                "properties": {
+                    "owner": {"bsonType": "objectId"},
+                    "day": {"bsonType": "string", "pattern": DATE_PATTERN},
+                    "events": {"bsonType": "array", "items": {"bsonType": "objectId"}},
+                    "updatedAt": {"bsonType": "date"}
+                }
+            }},
+        },
+        CollectionSpec {
+            name: "events",
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["title", "date", "logs", "booked", "day", "duration", "updatedAt"],
+                "properties": {
+                    "title": {"bsonType": "string"},
+                    "date": {"bsonType": "number"},
+                    "logs": {"bsonType": "array", "items": {
+                        "bsonType": "object",
+                        "required": ["duration", "title"],
+                        "properties": {
+                            "duration": {"bsonType": "number"},
+                            "title": {"bsonType": "string"}
+                        }
+                    }},
+                    "booked": {"bsonType": "bool"},
+                    "bookingDetails": {"bsonType": "array", "items": {
+                        "bsonType": "object",
+                        "required": ["_id", "toDate", "amount"],
+                        "properties": {
+                            "_id": {"bsonType": "objectId"},
+                            "toDate": {"bsonType": "string", "pattern": DATE_PATTERN},
+                            "amount": {"bsonType": "number", "minimum": 0}
+                        }
+                    }},
+                    "durationBooked": {"bsonType": "number", "minimum": 0},
+                    "day": {"bsonType": "objectId"},
+                    "duration": {"bsonType": "number", "minimum": 0},
+                    "updatedAt": {"bsonType": "date"}
+                }
+            }},
+        },
+        CollectionSpec {
+            name: "teams",
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["name", "members", "managers"],
+                "properties": {
+                    "name": {"bsonType": "string"},
+                    "members": {"bsonType": "array", "items": {"bsonType": "objectId"}},
+                    "managers": {"bsonType": "array", "items": {"bsonType": "objectId"}}
+                }
+            }},
+        },
+    ]
+}
+
+// Compares the declared indexes and validators with the database. With apply, missing
+// collections, indexes and validators are created and differing ones replaced, so running
+// it again changes nothing.
+pub async fn ensure_schema(
+    db: &Database,
+    apply: bool,
+) -> Result<Vec<SchemaDrift>, mongodb::error::Error> {
+    let mut drift: Vec<SchemaDrift> = vec![];
+    // Collections without indexes to list, as they don't exist
+    let mut missing_collections: Vec<&str> = vec![];
+
+    for spec in collections() {
+        let existing = db
+            .list_collections(doc! {"name": spec.name}, None)
+            .await?
+            .try_next()
+            .await?;
+        match existing {
+            None => {
+                if apply {
+                    let options = CreateCollectionOptions::builder()
+                        .validator(spec.validator.clone())
+                        .validation_level(ValidationLevel::Moderate)
+                        .validation_action(ValidationAction::Error)
+                        .build();
+                    db.create_collection(spec.name, options).await?;
+                } else {
+                    missing_collections.push(spec.name);
+                }
+                drift.push(SchemaDrift {
+                    collection: spec.name,
+                    target: spec.name.to_string(),
+                    kind: DriftKind::MissingCollection,
+                    fixed: apply,
+                });
+            }
+            Some(existing) => {
+                let options = existing.options;
+                if options.validator.as_ref() != Some(&spec.validator)
+                    || options.validation_level != Some(ValidationLevel::Moderate)
+                    || options.validation_action != Some(ValidationAction::Error)
+                {
+                    if apply {
+                        // Moderate validation leaves updates to already invalid documents alone
+                        db.run_command(
+                            doc! {
+                                "collMod": spec.name,
+                                "validator": spec.validator.clone(),
+                                "validationLevel": "moderate",
+                                "validationAction": "error"
+                            },
+                            None,
+                        )
+                        .await?;
+                    }
+                    drift.push(SchemaDrift {
+                        collection: spec.name,
+                        target: "$jsonSchema".to_string(),
+                        kind: DriftKind::ValidatorDiffers,
+                        fixed: apply,
+                    });
+                }
+            }
+        }
+    }
+
+    let indexes = required_indexes();
+    // The outbox is created along with its first entry, or by creating its index
+    let outbox_exists = !db
+        .list_collection_names(doc! {"name": "outbox"})
+        .await?
+        .is_empty();
+    if !outbox_exists {
+        missing_collections.push("outbox");
+    }
+    for collection_name in ["days", "events", "outbox"] {
+        let collection = db.collection::<Document>(collection_name);
+        let existing: Vec<IndexModel> = if missing_collections.contains(&collection_name) {
+            vec![]
+        } else {
+            collection.list_indexes(None).await?.try_collect().await?
+        };
+
+        for spec in indexes
+            .iter()
+            .filter(|spec| spec.collection == collection_name)
+        {
+            let current = existing
+                .iter()
+                .find(|index| index_name(index) == Some(spec.name));
+            let kind = match current {
+                Some(current)
+                    if same_keys(&current.keys, &spec.keys)
+                        && expire_after(current) == spec.expire_after =>
+                {
+                    continue
+                }
+                Some(_) => DriftKind::IndexDiffers,
+                None => DriftKind::MissingIndex,
+            };
+            if apply {
+                if kind == DriftKind::IndexDiffers {
+                    collection.drop_index(spec.name, None).await?;
+                }
+                let index = IndexModel::builder()
+                    .keys(spec.keys.clone())
+                    .options(
+                        IndexOptions::builder()
+                            .name(spec.name.to_string())
+                            .expire_after(spec.expire_after)
+                            .build(),
+                    )
+                    .build();
+                collection.create_index(index, None).await?;
+            }
+            drift.push(SchemaDrift {
+                collection: spec.collection,
+                target: spec.name.to_string(),
+                kind,
+                fixed: apply,
+            });
+        }
+
+        for index in &existing {
+            let name = index_name(index).unwrap_or_default();
+            if name != "_id_" && !indexes.iter().any(|spec| spec.name == name) {
+                drift.push(SchemaDrift {
+                    collection: collection_name,
+                    target: name.to_string(),
+                    kind: DriftKind::UnexpectedIndex,
+                    fixed: false,
+                });
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+// Runs at startup according to MONGO_SCHEMA_BOOTSTRAP, logging the drift found
+pub async fn bootstrap_schema(
+    db: &Database,
+    mode: SchemaBootstrap,
+) -> Result<(), mongodb::error::Error> {
+    if mode == SchemaBootstrap::Off {
+        return Ok(());
+    }
+    let drift = ensure_schema(db, mode == SchemaBootstrap::Apply).await?;
+    for item in &drift {
+        if item.fixed {
+            info!(
+                collection = item.collection,
+                "Schema bootstrap fixed {} {}",
+                item.kind.as_str(),
+                item.target
+            );
+        } else {
+            warn!(
+                collection = item.collection,
+                "Schema drift: {} {}, the schema subcommand applies the declared schema",
+                item.kind.as_str(),
+                item.target
+            );
+        }
+    }
+    Ok(())
+}
+
+fn index_name(index: &IndexModel) -> Option<&str> {
+    index
+        .options
+        .as_ref()
+        .and_then(|options| options.name.as_deref())
+}
+
+fn expire_after(index: &IndexModel) -> Option<Duration> {
+    index
+        .options
+        .as_ref()
+        .and_then(|options| options.expire_after)
+}
+
+// The server may return the key directions as int32, int64 or double
+fn same_keys(current: &Document, declared: &Document) -> bool {
+    current.len() == declared.len()
+        && current.iter().zip(declared.iter()).all(
+            |((current_field, current_direction), (declared_field, declared_direction))| {
+                current_field == declared_field
+                    && key_direction(current_direction) == key_direction(declared_direction)
+            },
+        )
+}
+
+fn key_direction(direction: &Bson) -> Option<f64> {
+    match direction {
+        Bson::Int32(direction) => Some(*direction as f64),
+        Bson::Int64(direction) => Some(*direction as f64),
+        Bson::Double(direction) => Some(*direction),
+        _ => None,
+    }
+}
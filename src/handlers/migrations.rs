@@ -0,0 +1,170 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::FindOptions,
+    Database,
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::models::mongo::AppliedMigration;
+
+// A versioned change to the stored documents. The update runs in batches on the documents
+// matching the filter, ordered by _id, so it has to be safe to run on part of them.
+struct Migration {
+    version: i32,
+    name: &'static str,
+    collection: &'static str,
+    filter: Document,
+    // An aggregation pipeline, so the new values can be computed from the document
+    update: Vec<Document>,
+}
+
+#[derive(Debug)]
+pub struct MigrationOutcome {
+    pub version: i32,
+    pub name: &'static str,
+    pub collection: &'static str,
+    pub already_applied: bool,
+    // Documents the migration applies to, counted up front on a dry run
+    pub matched: u64,
+    pub modified: u64,
+}
+
+#[derive(Deserialize)]
+struct IdOnly {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+}
+
+// Ordered by version. Released migrations must not be changed, add a new one instead.
+fn migrations() -> Vec<Migration> {
+    vec![
+        // Older events lack bookingDetails and durationBooked, which the booking logic
+        // otherwise has to default on every read
+        Migration {
+            version: 1,
+            name: "normalize_booking_details",
+            collection: "events",
+            filter: doc! {"$or": [
+                {"bookingDetails": null},
+                {"durationBooked": null}
+            ]},
+            update: vec![
+                doc! {"$set": {"bookingDetails": {"$ifNull": ["$bookingDetails", []]}}},
+                doc! {"$set": {"durationBooked": {"$ifNull": [
+                    "$durationBooked",
+                    {"$sum": "$bookingDetails.amount"}
+                ]}}},
+            ],
+        },
+    ]
+}
+
+// Runs the migrations that weren't applied yet in order and records each one once it
+// completed. A failed migration stops the run and is picked up again by the next one.
+pub async fn run_migrations(
+    db: &Database,
+    dry_run: bool,
+    batch_size: u32,
+) -> Result<Vec<MigrationOutcome>, mongodb::error::Error> {
+    let applied_collection = db.collection::<AppliedMigration>("migrations");
+    let applied: Vec<AppliedMigration> = applied_collection
+        .find(None, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut outcomes: Vec<MigrationOutcome> = vec![];
+    for migration in migrations() {
+        if applied.iter().any(|done| done.version == migration.version) {
+            outcomes.push(MigrationOutcome {
+                version: migration.version,
+                name: migration.name,
+                collection: migration.collection,
+                already_applied: true,
+                matched: 0,
+                modified: 0,
+            });
+            continue;
+        }
+
+        let collection = db.collection::<Document>(migration.collection);
+        if dry_run {
+            let matched = collection
+                .count_documents(migration.filter.clone(), None)
+                .await?;
+            outcomes.push(MigrationOutcome {
+                version: migration.version,
+                name: migration.name,
+                collection: migration.collection,
+                already_applied: false,
+                matched,
+                modified: 0,
+            });
+            continue;
+        }
+
+        let mut matched = 0;
+        let mut modified = 0;
+        let mut last_id: Option<ObjectId> = None;
+        // Paging by _id ends even if an update leaves the documents matching the filter
+        loop {
+            let mut filter = migration.filter.clone();
+            if let Some(last_id) = last_id {
+                filter.insert("_id", doc! {"$gt": last_id});
+            }
+            let options = FindOptions::builder()
+                .projection(doc! {"_id": 1})
+                .sort(doc! {"_id": 1})
+                .limit(batch_size as i64)
+                .build();
+            let batch: Vec<ObjectId> = collection
+                .clone_with_type::<IdOnly>()
+                .find(filter, options)
+                .await?
+                .map_ok(|document| document.id)
+                .try_collect()
+                .await?;
+            let Some(batch_last_id) = batch.last().copied() else {
+                break;
+            };
+
+            let result = collection
+                .update_many(
+                    doc! {"_id": {"$in": &batch}},
+                    migration.update.clone(),
+                    None,
+                )
+                .await?;
+            matched += result.matched_count;
+            modified += result.modified_count;
+            last_id = Some(batch_last_id);
+            info!(
+                version = migration.version,
+                "Migration {} updated {modified} of {matched} documents so far", migration.name
+            );
+        }
+
+        applied_collection
+            .insert_one(
+                AppliedMigration {
+                    version: migration.version,
+                    name: migration.name.to_string(),
+                    modified: modified as i64,
+                    appliedAt: DateTime::now(),
+                },
+                None,
+            )
+            .await?;
+        outcomes.push(MigrationOutcome {
+            version: migration.version,
+            name: migration.name,
+            collection: migration.collection,
+            already_applied: false,
+            matched,
+            modified,
+        });
+    }
+    Ok(outcomes)
+}
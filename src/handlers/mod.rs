@@ -1 +1,3 @@
+pub mod bootstrap;
+pub mod migrations;
 pub mod mongo;
@@ -1,55 +1,189 @@
+use actix_web::rt::time::sleep;
+use chrono::NaiveDate;
+use futures::TryStreamExt;
 use mongodb::{
-    bson::{doc, oid::ObjectId, DateTime},
-    options::{ClientOptions, FindOneAndUpdateOptions, ReturnDocument},
-    results::UpdateResult,
-    Client, Collection,
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    error::ErrorKind,
+    options::{
+        AggregateOptions, ClientOptions, FindOneAndUpdateOptions, FindOptions, ReturnDocument,
+        UpdateOptions,
+    },
+    results::{DeleteResult, UpdateResult},
+    Client, ClientSession, Collection, Cursor, Database,
 };
+use tokio::sync::broadcast;
+use tracing::{info, instrument, warn};
 
-use crate::models::mongo::{BookingDetail, Day, EventDocument};
+use crate::config::{MongoConfig, StartupMode};
+use crate::models::mongo::{
+    BookingDetail, Day, DeliveryStatus, EventDocument, FeedToken, OutboxEntry, Team, Webhook,
+    WebhookDelivery,
+};
+use crate::models::report::{BookingExportRow, BookingReport};
+use crate::services::metrics::Metrics;
+use crate::services::notifications::{BookingNotification, NotificationKind, Notifier};
+
+// Booking Details to add to a single Event, along with the Event's resulting booking state
+#[derive(Debug)]
+pub struct EventBookings {
+    pub event_id: ObjectId,
+    // Booking Details the Event had when it was read, the resulting state is only
+    // written if they are still the same number
+    pub read_booking_count: usize,
+    pub booking_details: Vec<BookingDetail>,
+    pub days: Vec<String>,
+    pub duration_booked: f32,
+    pub fully_booked: bool,
+}
 
 pub struct MongoDB {
+    client: Client,
+    db: Database,
     days: Collection<Day>,
     events: Collection<EventDocument>,
+    teams: Collection<Team>,
+    feed_tokens: Collection<FeedToken>,
+    webhooks: Collection<Webhook>,
+    webhook_deliveries: Collection<WebhookDelivery>,
+    outbox: Collection<OutboxEntry>,
+    notifier: Notifier,
+    metrics: Metrics,
 }
 
 impl MongoDB {
-    pub async fn init() -> Self {
-        let uri = if std::env::var("ENV").unwrap_or("development".to_string()) == "development" {
-            match std::env::var("MONGO_URI_DEV") {
-                Ok(uri) => uri,
-                Err(_) => format!("Error getting Dev Mongo URI!"),
-            }
-        } else {
-            match std::env::var("MONGO_URI") {
-                Ok(uri) => uri,
-                Err(_) => format!("Error getting Mongo URI!"),
+    // Connects according to the startup mode: fail-fast gives up after the first failed attempt,
+    // wait retries with exponential backoff. Invalid connection strings are never retried.
+    pub async fn init(
+        config: &MongoConfig,
+        metrics: Metrics,
+    ) -> Result<Self, mongodb::error::Error> {
+        let mut attempt: u32 = 0;
+        let mut backoff = config.startup_backoff;
+        let client = loop {
+            match Self::connect(config).await {
+                Ok(client) => break client,
+                Err(err) => {
+                    let retry = config.startup_mode == StartupMode::Wait
+                        && (config.startup_retries == 0 || attempt < config.startup_retries)
+                        && !matches!(*err.kind, ErrorKind::InvalidArgument { .. });
+                    if !retry {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    warn!(
+                        attempt,
+                        "Mongo DB is not reachable, retrying in {}ms: {err}",
+                        backoff.as_millis()
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(config.startup_max_backoff);
+                }
             }
         };
-        let client_options = ClientOptions::parse(uri).await.unwrap();
-        let host = client_options.hosts[0].clone();
-        let client = Client::with_options(client_options).expect("Connection to Mongo DB failed!");
-        println!("Connected to the following Mongo DB host: {host}");
-        let db = client.database("project-manager");
+
+        let db = client.database(&config.db_name);
         let days: Collection<Day> = db.collection("days");
         let events: Collection<EventDocument> = db.collection("events");
-        MongoDB { days, events }
+        let teams: Collection<Team> = db.collection("teams");
+        let feed_tokens: Collection<FeedToken> = db.collection("feedTokens");
+        let webhooks: Collection<Webhook> = db.collection("webhooks");
+        let webhook_deliveries: Collection<WebhookDelivery> = db.collection("webhookDeliveries");
+        let outbox: Collection<OutboxEntry> = db.collection("outbox");
+        Ok(MongoDB {
+            client,
+            db,
+            days,
+            events,
+            teams,
+            feed_tokens,
+            webhooks,
+            webhook_deliveries,
+            outbox,
+            notifier: Notifier::new(),
+            metrics,
+        })
+    }
+
+    async fn connect(config: &MongoConfig) -> Result<Client, mongodb::error::Error> {
+        let mut client_options = ClientOptions::parse(&config.uri).await?;
+        client_options.app_name = Some("booking-machine".to_string());
+        client_options.min_pool_size = config.min_pool_size;
+        client_options.max_pool_size = config.max_pool_size;
+        client_options.connect_timeout = Some(config.connect_timeout);
+        client_options.server_selection_timeout = Some(config.server_selection_timeout);
+        let host = client_options.hosts[0].clone();
+
+        let client = Client::with_options(client_options)?;
+        // The client connects lazily, so check that the server is reachable right away
+        client
+            .database("admin")
+            .run_command(doc! {"ping": 1}, None)
+            .await?;
+        info!("Connected to the following Mongo DB host: {host}");
+        Ok(client)
+    }
+
+    // Waits for the sessions and cursors still in use, then closes the connection pool
+    pub async fn shutdown(&self) {
+        self.client.clone().shutdown().await;
+    }
+
+    // Round trip to the server, used by the readiness probe
+    #[instrument(skip_all)]
+    pub async fn ping(&self) -> Result<(), mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("ping");
+        self.client
+            .database("admin")
+            .run_command(doc! {"ping": 1}, None)
+            .await?;
+        Ok(())
+    }
+
+    // Booking writes run in transactions, which a standalone mongod doesn't support
+    pub async fn check_transactions(&self) -> Result<(), String> {
+        let hello = self
+            .client
+            .database("admin")
+            .run_command(doc! {"hello": 1}, None)
+            .await
+            .map_err(|err| format!("Failed to read the Mongo DB topology: {err}"))?;
+        let is_replica_set = hello.contains_key("setName");
+        let is_sharded = hello.get_str("msg").is_ok_and(|msg| msg == "isdbgrid");
+        if is_replica_set || is_sharded {
+            Ok(())
+        } else {
+            Err("Mongo DB runs standalone, but booking writes need transactions, which need a replica set or a sharded cluster. Start mongod with --replSet, a single-node replica set is enough.".to_string())
+        }
     }
 
+    // For the maintenance tasks working on whole collections, see handlers::bootstrap
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn subscribe_notifications(&self) -> broadcast::Receiver<BookingNotification> {
+        self.notifier.subscribe()
+    }
+
+    #[instrument(skip_all)]
     pub async fn find_event_by_id(
         &self,
         event_id_str: &str,
     ) -> Result<Option<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_event_by_id");
         let event_id = ObjectId::parse_str(event_id_str).unwrap();
         let filter = doc! {"_id": event_id};
         self.events.find_one(filter, None).await
     }
 
+    #[instrument(skip_all)]
     pub async fn add_event_to_day(
         &self,
         owner_str: &str,
         day: &str,
         event_id: ObjectId,
     ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("add_event_to_day");
         let owner_id = ObjectId::parse_str(owner_str).unwrap();
         let filter = doc! {"owner": owner_id, "day": day};
         let update_opts = doc! {
@@ -63,14 +197,26 @@ impl MongoDB {
         self.days.update_one(filter, update_opts, None).await
     }
 
+    // The resulting booking state is only written if the Event still has the number of
+    // Booking Details it was read with, otherwise None is returned
+    #[instrument(skip_all)]
     pub async fn add_bookingdetail_to_event(
         &self,
         event_id: ObjectId,
+        read_booking_count: usize,
         booking_detail: BookingDetail,
         duration_booked: f32,
         fully_booked: bool,
+        outbox: &[OutboxEntry],
     ) -> Result<Option<EventDocument>, mongodb::error::Error> {
-        let filter = doc! {"_id": event_id};
+        let _timer = self.metrics.mongo_timer("add_bookingdetail_to_event");
+        let filter = doc! {
+            "_id": event_id,
+            "$expr": {"$eq": [
+                {"$size": {"$ifNull": ["$bookingDetails", []]}},
+                read_booking_count as i64
+            ]}
+        };
         let update_opts = doc! {
             "$set": {
                 "booked": fully_booked,
@@ -81,30 +227,36 @@ impl MongoDB {
                  "bookingDetails": bson::to_bson(&booking_detail).unwrap() // Custom types need to be manually converted to BSON https://stackoverflow.com/questions/67040094/save-nested-struct-with-rust-mongodb-returns-error-the-trait-fromt-is-not-im
             },
         };
-        let options = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
-            // .upsert(true)
-            .build();
-        self.events
-            .find_one_and_update(filter, update_opts, options)
-            .await
+        let event = self
+            .update_event_with_outbox(filter, update_opts, outbox)
+            .await?;
+        if let Some(event) = &event {
+            self.notify(NotificationKind::Added, event, &booking_detail)
+                .await;
+        }
+        Ok(event)
     }
 
+    #[instrument(skip_all)]
     pub async fn find_bookingdetail_by_id(
         &self,
         booking_id_str: &str,
     ) -> Result<Option<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_bookingdetail_by_id");
         let booking_id = ObjectId::parse_str(booking_id_str).unwrap();
         let filter = doc! {"bookingDetails._id": booking_id};
         self.events.find_one(filter, None).await
     }
 
+    #[instrument(skip_all)]
     pub async fn remove_bookingdetail_from_event(
         &self,
         event_id: ObjectId,
         booking_detail: &BookingDetail,
         duration_booked: f32,
+        outbox: &[OutboxEntry],
     ) -> Result<Option<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("remove_bookingdetail_from_event");
         let filter = doc! {"_id": event_id};
         let update_opts = doc! {
             "$set": {
@@ -116,20 +268,212 @@ impl MongoDB {
                  "bookingDetails": bson::to_bson(&booking_detail).unwrap() // Custom types need to be manually converted to BSON https://stackoverflow.com/questions/67040094/save-nested-struct-with-rust-mongodb-returns-error-the-trait-fromt-is-not-im
             },
         };
+        let event = self
+            .update_event_with_outbox(filter, update_opts, outbox)
+            .await?;
+        if let Some(event) = &event {
+            self.notify(NotificationKind::Removed, event, booking_detail)
+                .await;
+        }
+        Ok(event)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_days(
+        &self,
+        owner_str: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<Day>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_days");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"owner": owner_id, "day": {"$gte": from, "$lte": to}};
+        let options = FindOptions::builder().sort(doc! {"day": 1}).build();
+        self.days.find(filter, options).await?.try_collect().await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_day_by_id(
+        &self,
+        day_id: ObjectId,
+    ) -> Result<Option<Day>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_day_by_id");
+        let filter = doc! {"_id": day_id};
+        self.days.find_one(filter, None).await
+    }
+
+    // Streams every Day, for the consistency checker
+    #[instrument(skip_all)]
+    pub async fn find_all_days(&self) -> Result<Cursor<Day>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_all_days");
+        self.days.find(None, None).await
+    }
+
+    // Streams every Event, for the consistency checker
+    #[instrument(skip_all)]
+    pub async fn find_all_events(&self) -> Result<Cursor<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_all_events");
+        self.events.find(None, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn add_event_to_day_by_id(
+        &self,
+        day_id: ObjectId,
+        event_id: ObjectId,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("add_event_to_day_by_id");
+        let filter = doc! {"_id": day_id};
+        let update_opts = doc! {
+            "$addToSet": {
+                "events": event_id
+            },
+            "$set": {
+                "updatedAt": DateTime::now()
+            }
+        };
+        self.days.update_one(filter, update_opts, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn remove_event_from_day_by_id(
+        &self,
+        day_id: ObjectId,
+        event_id: ObjectId,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("remove_event_from_day_by_id");
+        let filter = doc! {"_id": day_id};
+        let update_opts = doc! {
+            "$pull": {
+                "events": event_id
+            },
+            "$set": {
+                "updatedAt": DateTime::now()
+            }
+        };
+        self.days.update_one(filter, update_opts, None).await
+    }
+
+    // Overwrites the booking state derived from the Booking Details, used by repairs.
+    // Only matches if the booking state is still the one that was read.
+    #[instrument(skip_all)]
+    pub async fn set_event_booking_state(
+        &self,
+        event: &EventDocument,
+        duration_booked: f32,
+        fully_booked: bool,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("set_event_booking_state");
+        let filter = doc! {
+            "_id": event.id,
+            "durationBooked": event.durationBooked,
+            "bookingDetails": bson::to_bson(&event.bookingDetails).unwrap()
+        };
+        let update_opts = doc! {
+            "$set": {
+                "booked": fully_booked,
+                "durationBooked": duration_booked,
+                "updatedAt": DateTime::now()
+            }
+        };
+        self.events.update_one(filter, update_opts, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_events_by_ids(
+        &self,
+        event_ids: &[ObjectId],
+    ) -> Result<Vec<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_events_by_ids");
+        let filter = doc! {"_id": {"$in": event_ids}};
+        let options = FindOptions::builder().sort(doc! {"date": 1}).build();
+        self.events.find(filter, options).await?.try_collect().await
+    }
+
+    // Replaces a Booking Detail in place, matched by its ID
+    #[instrument(skip_all)]
+    pub async fn replace_bookingdetail_in_event(
+        &self,
+        event_id: ObjectId,
+        booking_detail: &BookingDetail,
+        duration_booked: f32,
+        fully_booked: bool,
+        outbox: &[OutboxEntry],
+    ) -> Result<Option<EventDocument>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("replace_bookingdetail_in_event");
+        let filter = doc! {"_id": event_id, "bookingDetails._id": booking_detail.id};
+        let update_opts = doc! {
+            "$set": {
+                "booked": fully_booked,
+                "durationBooked": duration_booked,
+                "updatedAt": DateTime::now(),
+                "bookingDetails.$": bson::to_bson(booking_detail).unwrap()
+            },
+        };
+        let event = self
+            .update_event_with_outbox(filter, update_opts, outbox)
+            .await?;
+        if let Some(event) = &event {
+            self.notify(NotificationKind::Updated, event, booking_detail)
+                .await;
+        }
+        Ok(event)
+    }
+
+    // Updates an Event and writes the outbox entries of the change in the same transaction.
+    // Nothing is written if the Event is not found.
+    async fn update_event_with_outbox(
+        &self,
+        filter: Document,
+        update_opts: Document,
+        outbox: &[OutboxEntry],
+    ) -> Result<Option<EventDocument>, mongodb::error::Error> {
+        let mut session = self.client.start_session(None).await?;
+        session.start_transaction(None).await?;
+
         let options = FindOneAndUpdateOptions::builder()
             .return_document(ReturnDocument::After)
             .build();
-        self.events
-            .find_one_and_update(filter, update_opts, options)
+        let event = match self
+            .events
+            .find_one_and_update_with_session(filter, update_opts, options, &mut session)
             .await
+        {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                abort_transaction(&mut session).await;
+                return Ok(None);
+            }
+            Err(err) => {
+                abort_transaction(&mut session).await;
+                return Err(err);
+            }
+        };
+
+        if !outbox.is_empty() {
+            if let Err(err) = self
+                .outbox
+                .insert_many_with_session(outbox, None, &mut session)
+                .await
+            {
+                abort_transaction(&mut session).await;
+                return Err(err);
+            }
+        }
+
+        session.commit_transaction().await?;
+        self.metrics.record_domain_events(outbox);
+        Ok(Some(event))
     }
 
+    #[instrument(skip_all)]
     pub async fn remove_event_from_day(
         &self,
         owner_str: &str,
         day: &String,
         event_id: ObjectId,
     ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("remove_event_from_day");
         let owner_id = ObjectId::parse_str(owner_str).unwrap();
         let filter = doc! {"day": day, "owner": owner_id };
         let update_opts = doc! {
@@ -142,4 +486,604 @@ impl MongoDB {
         };
         self.days.update_one(filter, update_opts, None).await
     }
+
+    // Adds the Booking Details to their Events and the Events to every destination Day in a single transaction.
+    // Nothing is written, and None returned, if an Event is gone or got other Booking Details meanwhile.
+    #[instrument(skip_all)]
+    pub async fn add_bookingdetails_atomically(
+        &self,
+        owner_str: &str,
+        batch: &[EventBookings],
+        outbox: &[OutboxEntry],
+    ) -> Result<Option<Vec<EventDocument>>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("add_bookingdetails_atomically");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let mut session = self.client.start_session(None).await?;
+        session.start_transaction(None).await?;
+
+        let mut events = vec![];
+        for event_bookings in batch {
+            for day in &event_bookings.days {
+                let filter = doc! {"owner": owner_id, "day": day};
+                let update_opts = doc! {
+                    "$addToSet" :{
+                        "events": event_bookings.event_id
+                    },
+                    "$set": {
+                        "updatedAt": DateTime::now()
+                    }
+                };
+                if let Err(err) = self
+                    .days
+                    .update_one_with_session(filter, update_opts, None, &mut session)
+                    .await
+                {
+                    abort_transaction(&mut session).await;
+                    return Err(err);
+                }
+            }
+
+            let filter = doc! {
+                "_id": event_bookings.event_id,
+                "$expr": {"$eq": [
+                    {"$size": {"$ifNull": ["$bookingDetails", []]}},
+                    event_bookings.read_booking_count as i64
+                ]}
+            };
+            let update_opts = doc! {
+                "$set": {
+                    "booked": event_bookings.fully_booked,
+                    "durationBooked": event_bookings.duration_booked,
+                    "updatedAt": DateTime::now()
+                },
+                "$push" :{
+                     "bookingDetails": {
+                         "$each": bson::to_bson(&event_bookings.booking_details).unwrap()
+                     }
+                },
+            };
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+            match self
+                .events
+                .find_one_and_update_with_session(filter, update_opts, options, &mut session)
+                .await
+            {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {
+                    abort_transaction(&mut session).await;
+                    return Ok(None);
+                }
+                Err(err) => {
+                    abort_transaction(&mut session).await;
+                    return Err(err);
+                }
+            };
+        }
+
+        if !outbox.is_empty() {
+            if let Err(err) = self
+                .outbox
+                .insert_many_with_session(outbox, None, &mut session)
+                .await
+            {
+                abort_transaction(&mut session).await;
+                return Err(err);
+            }
+        }
+
+        session.commit_transaction().await?;
+        self.metrics.record_domain_events(outbox);
+
+        for (event, event_bookings) in events.iter().zip(batch) {
+            for booking_detail in &event_bookings.booking_details {
+                self.notify(NotificationKind::Added, event, booking_detail)
+                    .await;
+            }
+        }
+        Ok(Some(events))
+    }
+
+    // Publishes to the streams of the owner of the event's Day. The change is already saved,
+    // so a failed lookup only costs the notification.
+    async fn notify(
+        &self,
+        kind: NotificationKind,
+        event: &EventDocument,
+        booking_detail: &BookingDetail,
+    ) {
+        let owner = match self.find_day_by_id(event.day).await {
+            Ok(Some(day)) => day.owner,
+            Ok(None) => {
+                warn!(event_id = %event.id, "No notification sent, the Event's Day doesn't exist");
+                return;
+            }
+            Err(err) => {
+                warn!(event_id = %event.id, "No notification sent, failed to fetch the Event's Day: {err}");
+                return;
+            }
+        };
+        self.notifier
+            .publish(BookingNotification::new(kind, owner, event, booking_detail));
+    }
+
+    // Sums the amounts of all Booking Details the user booked onto the given day, across all of their events
+    #[instrument(skip_all)]
+    pub async fn find_booked_amount_for_day(
+        &self,
+        owner_str: &str,
+        day: &str,
+    ) -> Result<f32, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_booked_amount_for_day");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let pipeline = vec![
+            doc! {"$match": {"bookingDetails.toDate": day}},
+            owner_lookup_stage(),
+            doc! {"$match": {"dayDoc.owner": owner_id}},
+            doc! {"$unwind": "$bookingDetails"},
+            doc! {"$match": {"bookingDetails.toDate": day}},
+            doc! {"$group": {"_id": null, "total": {"$sum": "$bookingDetails.amount"}}},
+        ];
+        let mut cursor = self.events.aggregate(pipeline, None).await?;
+        let total = match cursor.try_next().await? {
+            Some(result) => match result.get("total") {
+                Some(Bson::Double(total)) => *total as f32,
+                Some(Bson::Int32(total)) => *total as f32,
+                Some(Bson::Int64(total)) => *total as f32,
+                _ => 0.0,
+            },
+            None => 0.0,
+        };
+        Ok(total)
+    }
+
+    // Aggregates the user's booked hours per day, ISO week, month and event, and lists the events with unbooked hours.
+    // The optional "YYYY-MM-DD" range applies to the Booking Details' destination days and to the event dates.
+    #[instrument(skip_all)]
+    pub async fn booking_report(
+        &self,
+        owner_str: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<BookingReport, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("booking_report");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+
+        let mut to_date_range = Document::new();
+        let mut event_date_range = Document::new();
+        if let Some(from) = from {
+            to_date_range.insert("$gte", from);
+            event_date_range.insert("$gte", day_start_millis(from));
+        }
+        if let Some(to) = to {
+            to_date_range.insert("$lte", to);
+            event_date_range.insert("$lt", day_start_millis(to) + 86_400_000.0);
+        }
+
+        let mut bookings = vec![doc! {"$unwind": "$bookingDetails"}];
+        if !to_date_range.is_empty() {
+            bookings.push(doc! {"$match": {"bookingDetails.toDate": to_date_range}});
+        }
+        bookings.push(doc! {"$addFields": {
+            "toDate": {"$dateFromString": {"dateString": "$bookingDetails.toDate", "format": "%Y-%m-%d"}}
+        }});
+
+        let facet = |stages: Vec<Document>| {
+            bookings
+                .iter()
+                .cloned()
+                .chain(stages)
+                .map(Bson::Document)
+                .collect::<Vec<Bson>>()
+        };
+
+        let by_day = facet(vec![
+            doc! {"$group": {"_id": "$bookingDetails.toDate", "amount": {"$sum": "$bookingDetails.amount"}}},
+            doc! {"$project": {"_id": 0, "day": "$_id", "amount": 1}},
+            doc! {"$sort": {"day": 1}},
+        ]);
+        let by_week = facet(vec![
+            doc! {"$group": {
+                "_id": {"isoWeekYear": {"$isoWeekYear": "$toDate"}, "isoWeek": {"$isoWeek": "$toDate"}},
+                "amount": {"$sum": "$bookingDetails.amount"}
+            }},
+            doc! {"$project": {"_id": 0, "isoWeekYear": "$_id.isoWeekYear", "isoWeek": "$_id.isoWeek", "amount": 1}},
+            doc! {"$sort": {"isoWeekYear": 1, "isoWeek": 1}},
+        ]);
+        let by_month = facet(vec![
+            doc! {"$group": {
+                "_id": {"$dateToString": {"format": "%Y-%m", "date": "$toDate"}},
+                "amount": {"$sum": "$bookingDetails.amount"}
+            }},
+            doc! {"$project": {"_id": 0, "month": "$_id", "amount": 1}},
+            doc! {"$sort": {"month": 1}},
+        ]);
+        let by_event = facet(vec![
+            doc! {"$group": {
+                "_id": "$_id",
+                "title": {"$first": "$title"},
+                "amount": {"$sum": "$bookingDetails.amount"}
+            }},
+            doc! {"$project": {"_id": 0, "eventId": "$_id", "title": 1, "amount": 1}},
+            doc! {"$sort": {"title": 1}},
+        ]);
+
+        let mut partially_booked = vec![];
+        if !event_date_range.is_empty() {
+            partially_booked.push(doc! {"$match": {"date": event_date_range}});
+        }
+        partially_booked.extend([
+            doc! {"$addFields": {"durationBooked": {"$ifNull": ["$durationBooked", 0]}}},
+            doc! {"$match": {"$expr": {"$lt": ["$durationBooked", "$duration"]}}},
+            doc! {"$project": {"title": 1, "date": 1, "duration": 1, "durationBooked": 1}},
+            doc! {"$sort": {"date": 1}},
+        ]);
+
+        let pipeline = vec![
+            owner_lookup_stage(),
+            doc! {"$match": {"dayDoc.owner": owner_id}},
+            doc! {"$facet": {
+                "byDay": by_day,
+                "byWeek": by_week,
+                "byMonth": by_month,
+                "byEvent": by_event,
+                "partiallyBooked": partially_booked,
+            }},
+        ];
+
+        let mut cursor = self.events.aggregate(pipeline, None).await?;
+        match cursor.try_next().await? {
+            Some(result) => Ok(bson::from_document(result)?),
+            None => Ok(BookingReport::default()),
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_team_by_id(
+        &self,
+        team_id_str: &str,
+    ) -> Result<Option<Team>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_team_by_id");
+        let team_id = ObjectId::parse_str(team_id_str).unwrap();
+        let filter = doc! {"_id": team_id};
+        self.teams.find_one(filter, None).await
+    }
+
+    // Streams the Booking Details of the given users as flat export rows, ordered by destination day.
+    // The optional "YYYY-MM-DD" range applies to the Booking Details' destination days.
+    #[instrument(skip_all)]
+    pub async fn export_booking_rows(
+        &self,
+        owners: Vec<ObjectId>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Cursor<BookingExportRow>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("export_booking_rows");
+        let mut to_date_range = Document::new();
+        if let Some(from) = from {
+            to_date_range.insert("$gte", from);
+        }
+        if let Some(to) = to {
+            to_date_range.insert("$lte", to);
+        }
+
+        let mut pipeline = vec![];
+        if !to_date_range.is_empty() {
+            pipeline.push(doc! {"$match": {"bookingDetails.toDate": to_date_range.clone()}});
+        }
+        pipeline.extend([
+            owner_lookup_stage(),
+            doc! {"$match": {"dayDoc.owner": {"$in": owners}}},
+            doc! {"$unwind": "$bookingDetails"},
+        ]);
+        if !to_date_range.is_empty() {
+            pipeline.push(doc! {"$match": {"bookingDetails.toDate": to_date_range}});
+        }
+        pipeline.extend([
+            doc! {"$sort": {"bookingDetails.toDate": 1, "date": 1}},
+            doc! {"$project": {
+                "_id": 0,
+                "userId": {"$arrayElemAt": ["$dayDoc.owner", 0]},
+                "eventTitle": "$title",
+                "eventDate": "$date",
+                "toDate": "$bookingDetails.toDate",
+                "amount": "$bookingDetails.amount",
+                "bookingId": "$bookingDetails._id"
+            }},
+        ]);
+
+        let options = AggregateOptions::builder().allow_disk_use(true).build();
+        let cursor = self.events.aggregate(pipeline, options).await?;
+        Ok(cursor.with_type::<BookingExportRow>())
+    }
+
+    // Replaces the user's calendar feed token, which invalidates the previous one
+    #[instrument(skip_all)]
+    pub async fn replace_feed_token(
+        &self,
+        owner_str: &str,
+        token_hash: String,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("replace_feed_token");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"owner": owner_id};
+        let update_opts = doc! {
+            "$set": {
+                "tokenHash": token_hash,
+                "createdAt": DateTime::now()
+            }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        self.feed_tokens
+            .update_one(filter, update_opts, options)
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_feed_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<FeedToken>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_feed_token");
+        let filter = doc! {"tokenHash": token_hash};
+        self.feed_tokens.find_one(filter, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn insert_webhook(&self, webhook: &Webhook) -> Result<(), mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("insert_webhook");
+        self.webhooks.insert_one(webhook, None).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_webhooks(
+        &self,
+        owner_str: &str,
+    ) -> Result<Vec<Webhook>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_webhooks");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"owner": owner_id};
+        let options = FindOptions::builder().sort(doc! {"createdAt": 1}).build();
+        self.webhooks
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_webhook_by_id(
+        &self,
+        webhook_id: ObjectId,
+    ) -> Result<Option<Webhook>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_webhook_by_id");
+        let filter = doc! {"_id": webhook_id};
+        self.webhooks.find_one(filter, None).await
+    }
+
+    // Only returns the webhook if it belongs to the owner
+    #[instrument(skip_all)]
+    pub async fn find_owned_webhook(
+        &self,
+        owner_str: &str,
+        webhook_id: ObjectId,
+    ) -> Result<Option<Webhook>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_owned_webhook");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"_id": webhook_id, "owner": owner_id};
+        self.webhooks.find_one(filter, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn delete_webhook(
+        &self,
+        owner_str: &str,
+        webhook_id: ObjectId,
+    ) -> Result<DeleteResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("delete_webhook");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"_id": webhook_id, "owner": owner_id};
+        self.webhooks.delete_one(filter, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn find_subscribed_webhooks(
+        &self,
+        owner_str: &str,
+        event: &str,
+    ) -> Result<Vec<Webhook>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_subscribed_webhooks");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let filter = doc! {"owner": owner_id, "active": true, "events": event};
+        self.webhooks.find(filter, None).await?.try_collect().await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn insert_webhook_deliveries(
+        &self,
+        deliveries: &[WebhookDelivery],
+    ) -> Result<(), mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("insert_webhook_deliveries");
+        self.webhook_deliveries
+            .insert_many(deliveries, None)
+            .await?;
+        Ok(())
+    }
+
+    // Takes the oldest due delivery and pushes its next attempt back to lease_until,
+    // so that no other worker picks it up while it is being sent
+    #[instrument(skip_all)]
+    pub async fn claim_due_webhook_delivery(
+        &self,
+        lease_until: DateTime,
+    ) -> Result<Option<WebhookDelivery>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("claim_due_webhook_delivery");
+        let filter = doc! {
+            "status": DeliveryStatus::Pending.as_str(),
+            "nextAttemptAt": {"$lte": DateTime::now()}
+        };
+        let update_opts = doc! {
+            "$set": {
+                "nextAttemptAt": lease_until
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! {"nextAttemptAt": 1})
+            .build();
+        self.webhook_deliveries
+            .find_one_and_update(filter, update_opts, options)
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn record_webhook_delivery_attempt(
+        &self,
+        delivery_id: ObjectId,
+        status: DeliveryStatus,
+        next_attempt_at: DateTime,
+        status_code: Option<i32>,
+        error: Option<String>,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("record_webhook_delivery_attempt");
+        let filter = doc! {"_id": delivery_id};
+        let update_opts = doc! {
+            "$set": {
+                "status": status.as_str(),
+                "nextAttemptAt": next_attempt_at,
+                "lastAttemptAt": DateTime::now(),
+                "lastStatusCode": status_code,
+                "lastError": error
+            },
+            "$inc": {
+                "attempts": 1
+            }
+        };
+        self.webhook_deliveries
+            .update_one(filter, update_opts, None)
+            .await
+    }
+
+    // Latest deliveries first
+    #[instrument(skip_all)]
+    pub async fn find_webhook_deliveries(
+        &self,
+        owner_str: &str,
+        webhook_id: ObjectId,
+        status: Option<DeliveryStatus>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("find_webhook_deliveries");
+        let owner_id = ObjectId::parse_str(owner_str).unwrap();
+        let mut filter = doc! {"owner": owner_id, "webhookId": webhook_id};
+        if let Some(status) = status {
+            filter.insert("status", status.as_str());
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1})
+            .limit(limit)
+            .build();
+        self.webhook_deliveries
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    // Takes the oldest due entry and pushes its next attempt back to lease_until,
+    // so that no other relay worker picks it up while it is being relayed
+    #[instrument(skip_all)]
+    pub async fn claim_due_outbox_entry(
+        &self,
+        lease_until: DateTime,
+    ) -> Result<Option<OutboxEntry>, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("claim_due_outbox_entry");
+        let filter = doc! {
+            "status": DeliveryStatus::Pending.as_str(),
+            "nextAttemptAt": {"$lte": DateTime::now()}
+        };
+        let update_opts = doc! {
+            "$set": {
+                "nextAttemptAt": lease_until
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! {"nextAttemptAt": 1})
+            .build();
+        self.outbox
+            .find_one_and_update(filter, update_opts, options)
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn mark_outbox_sink_delivered(
+        &self,
+        entry_id: ObjectId,
+        sink: &str,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("mark_outbox_sink_delivered");
+        let filter = doc! {"_id": entry_id};
+        let update_opts = doc! {
+            "$addToSet": {
+                "deliveredSinks": sink
+            }
+        };
+        self.outbox.update_one(filter, update_opts, None).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn record_outbox_attempt(
+        &self,
+        entry_id: ObjectId,
+        status: DeliveryStatus,
+        next_attempt_at: DateTime,
+        error: Option<String>,
+    ) -> Result<UpdateResult, mongodb::error::Error> {
+        let _timer = self.metrics.mongo_timer("record_outbox_attempt");
+        let relayed_at = match status {
+            DeliveryStatus::Delivered => Some(DateTime::now()),
+            _ => None,
+        };
+        let filter = doc! {"_id": entry_id};
+        let update_opts = doc! {
+            "$set": {
+                "status": status.as_str(),
+                "nextAttemptAt": next_attempt_at,
+                "lastError": error,
+                "relayedAt": relayed_at
+            },
+            "$inc": {
+                "attempts": 1
+            }
+        };
+        self.outbox.update_one(filter, update_opts, None).await
+    }
+}
+
+// Joins the Day an Event belongs to, as Events are only owned through their Day
+fn owner_lookup_stage() -> Document {
+    doc! {"$lookup": {
+        "from": "days",
+        "localField": "day",
+        "foreignField": "_id",
+        "as": "dayDoc"
+    }}
+}
+
+fn day_start_millis(day: &str) -> f64 {
+    NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis() as f64
+}
+
+// A failed abort is only logged, so the error which caused it is the one returned.
+// The server drops the transaction once it times out anyway.
+async fn abort_transaction(session: &mut ClientSession) {
+    if let Err(err) = session.abort_transaction().await {
+        warn!("Failed to abort the transaction: {err}");
+    }
 }
@@ -0,0 +1,9 @@
+// Shared by the server and the admin CLI, see src/bin
+pub mod api;
+pub mod commands;
+pub mod config;
+pub mod handlers;
+pub mod middlewares;
+pub mod models;
+pub mod services;
+pub mod telemetry;
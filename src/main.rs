@@ -1,51 +1,160 @@
-use actix_cors::Cors;
-use actix_web::{http::header, middleware::Logger, web::Data, App, HttpServer};
-use dotenv::dotenv;
+use std::time::Duration;
 
-mod api;
-mod handlers;
-mod middlewares;
-mod models;
+use actix_web::{
+    rt::time::timeout,
+    web::{self, Data},
+    App, HttpServer,
+};
+use clap::Parser;
 
-use api::routes::{book_event, delete_event, health};
-use handlers::mongo::MongoDB;
-use middlewares::auth::CheckLoginFactory;
+use booking_machine::api::graphql::build_schema;
+use booking_machine::api::openapi::ApiDoc;
+use booking_machine::api::routes::{livez, metrics, readyz};
+use booking_machine::api::routes_config::{legacy_routes, routes};
+use booking_machine::commands::run_command;
+use booking_machine::config::{Cli, Config};
+use booking_machine::handlers::bootstrap::bootstrap_schema;
+use booking_machine::handlers::mongo::MongoDB;
+use booking_machine::middlewares::cors::cors;
+use booking_machine::middlewares::deprecation::DeprecatedAliasFactory;
+use booking_machine::middlewares::metrics::RequestMetricsFactory;
+use booking_machine::middlewares::request_id::RequestTracingFactory;
+use booking_machine::services::metrics::Metrics;
+use booking_machine::services::outbox::{build_sinks, run_relay_worker};
+use booking_machine::services::shutdown::shutdown_channel;
+use booking_machine::services::webhooks::run_delivery_worker;
+use booking_machine::telemetry::init_telemetry;
+use tracing::{error, info};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+// 2026-10-19T00:00:00Z, when the routes moved under /api/v1
+const V1_ALIASES_DEPRECATED_AT: i64 = 1792368000;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let port: u16 = match std::env::var("PORT") {
-        Ok(port) => port.parse().unwrap_or_default(),
-        Err(_) => 8081,
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    // Kept until the server stops, dropping it flushes the OTLP spans
+    let _telemetry = match init_telemetry(&config.log) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Failed to initialize the logging: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let booking_rules_data = Data::new(config.booking_rules.clone());
+
+    let openapi = ApiDoc::openapi();
+
+    let metrics_data = Data::new(Metrics::new());
+
+    let mongo = match MongoDB::init(&config.mongo, metrics_data.as_ref().clone()).await {
+        Ok(mongo) => mongo,
+        Err(err) => {
+            error!("Failed to connect to Mongo DB: {err}");
+            std::process::exit(1);
+        }
     };
 
-    let env = std::env::var("ENV").unwrap_or("development".to_string());
-    if env == "development" {
-        dotenv().ok();
-        env_logger::init();
+    if let Some(command) = &cli.command {
+        let code = run_command(command, &mongo).await;
+        mongo.shutdown().await;
+        std::process::exit(code);
+    }
+
+    if let Err(err) = bootstrap_schema(mongo.database(), config.mongo.schema_bootstrap).await {
+        error!("Failed to bootstrap the indexes and validators: {err}");
+        std::process::exit(1);
     }
 
-    let origin_url = std::env::var("ORIGIN").expect("Origin env variable is required.");
+    if let Err(err) = mongo.check_transactions().await {
+        error!("{err}");
+        std::process::exit(1);
+    }
 
-    let mongo = MongoDB::init().await;
     let mongo_data = Data::new(mongo);
 
-    println!("Starting the Booking Machine server in ENV '{env}' on PORT {port}!");
-    HttpServer::new(move || {
+    let outbox_sinks = build_sinks(mongo_data.clone(), &config.outbox_sinks)
+        .expect("Invalid outbox configuration");
+    let (shutdown_trigger, shutdown) = shutdown_channel();
+    let workers = [
+        actix_web::rt::spawn(run_relay_worker(
+            mongo_data.clone(),
+            outbox_sinks,
+            shutdown.clone(),
+        )),
+        actix_web::rt::spawn(run_delivery_worker(
+            mongo_data.clone(),
+            config.webhook_allow_private_targets,
+            shutdown,
+        )),
+    ];
+
+    let schema_data = Data::new(build_schema(mongo_data.clone(), booking_rules_data.clone()));
+
+    let port = config.port;
+    let shutdown_timeout = config.shutdown_timeout;
+    info!(
+        "Starting the Booking Machine server in ENV '{}' on PORT {port}!",
+        config.env.as_str()
+    );
+    let config_data = Data::new(config);
+    let app_mongo_data = mongo_data.clone();
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(CheckLoginFactory)
-            .wrap(
-                Cors::default()
-                    .allowed_origin(&origin_url)
-                    .allowed_methods(vec!["GET", "POST", "DELETE"])
-                    .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT]),
+            .wrap(cors(&config_data.cors))
+            .wrap(RequestMetricsFactory {
+                metrics: metrics_data.as_ref().clone(),
+            })
+            .wrap(RequestTracingFactory)
+            .app_data(config_data.clone())
+            .app_data(app_mongo_data.clone())
+            .app_data(metrics_data.clone())
+            .app_data(booking_rules_data.clone())
+            .app_data(schema_data.clone())
+            .service(web::scope("/api/v1").configure(routes))
+            .service(livez)
+            .service(readyz)
+            .service(metrics)
+            .service(
+                SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
+            )
+            // Unversioned aliases of the original v1 routes, kept for already deployed clients
+            .service(
+                web::scope("")
+                    .wrap(DeprecatedAliasFactory {
+                        deprecated_at: V1_ALIASES_DEPRECATED_AT,
+                        successor_prefix: "/api/v1",
+                    })
+                    .configure(legacy_routes),
             )
-            .wrap(Logger::default())
-            .app_data(mongo_data.clone())
-            .service(health)
-            .service(book_event)
-            .service(delete_event)
     })
+    // On SIGINT/SIGTERM the server stops accepting connections and waits up to the shutdown
+    // timeout for in-flight requests, so booking changes aren't cut off halfway
+    .shutdown_timeout(shutdown_timeout)
     .bind(("0.0.0.0", port))?
     .run()
-    .await
+    .await;
+
+    info!("Server stopped, waiting for the background workers");
+    shutdown_trigger.trigger();
+    for worker in workers {
+        match timeout(Duration::from_secs(shutdown_timeout), worker).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => error!("Background worker failed: {err}"),
+            Err(_) => error!("Background worker did not stop within the shutdown timeout"),
+        }
+    }
+    mongo_data.shutdown().await;
+    info!("Closed the Mongo DB connections");
+
+    server
 }
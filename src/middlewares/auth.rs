@@ -1,19 +1,20 @@
-use std::{
-    env,
-    future::{ready, Ready},
-};
+use std::future::{ready, Ready};
 
 use actix_web::{
     body::EitherBody,
     dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    web::Data,
     Error, HttpMessage, HttpResponse,
 };
 use futures_util::future::LocalBoxFuture;
 use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use urlencoding::decode as url_decode;
 
 use crate::api::routes_structs::ErrorResPayload;
+use crate::config::Config;
+use crate::services::metrics::Metrics;
 
 pub struct CheckLoginFactory;
 
@@ -44,6 +45,10 @@ struct Claims {
 #[derive(Debug, Clone)]
 pub struct UserId(pub String);
 
+// The same user ID, parsed once by CheckLogin for the routes working with ObjectIds
+#[derive(Debug, Clone, Copy)]
+pub struct UserObjectId(pub ObjectId);
+
 pub struct CheckLoginMiddleware<S> {
     service: S,
 }
@@ -61,7 +66,7 @@ where
     dev::forward_ready!(service);
 
     fn call(&self, request: ServiceRequest) -> Self::Future {
-        let mut is_logged_in = false;
+        let mut user_oid = None;
 
         let url_encoded_token = match request.headers().get("Authorization") {
             Some(auth_header) => match auth_header.to_str() {
@@ -78,42 +83,67 @@ where
             ""
         };
 
-        let key = env::var("USER_AUTH").expect("JWT User secret not set!");
+        let key = request
+            .app_data::<Data<Config>>()
+            .expect("Config is not registered!")
+            .user_auth
+            .clone();
 
         let validation = Validation::new(Algorithm::HS256);
 
+        let metrics = request.app_data::<Data<Metrics>>().cloned();
+
         let token_data = match decode::<Claims>(
-            &token,
+            token,
             &DecodingKey::from_secret(&key.into_bytes()),
             &validation,
         ) {
-            Ok(c) => {
-                is_logged_in = true;
-                c.claims._id
-            }
-            Err(err) => match *err.kind() {
-                ErrorKind::InvalidToken => "- Token is invalid".into(), // Example on how to handle a specific error
-                ErrorKind::ExpiredSignature => "- Token expired".into(), // Example on how to handle a specific error
-                ErrorKind::InvalidSignature => "- Token signature is invalid".into(), // Example on how to handle a specific error
-                _ => "- Some other errors".into(),
+            // Tokens of users whose ID is not an ObjectId are rejected like invalid ones
+            Ok(c) => match ObjectId::parse_str(&c.claims._id) {
+                Ok(oid) => {
+                    user_oid = Some(oid);
+                    c.claims._id
+                }
+                Err(_) => {
+                    if let Some(metrics) = &metrics {
+                        metrics.auth_failure("invalid_user_id");
+                    }
+                    "- Token user ID is invalid".into()
+                }
             },
+            Err(err) => {
+                if let Some(metrics) = &metrics {
+                    metrics.auth_failure(failure_reason(err.kind()));
+                }
+                match *err.kind() {
+                    ErrorKind::InvalidToken => "- Token is invalid".into(), // Example on how to handle a specific error
+                    ErrorKind::ExpiredSignature => "- Token expired".into(), // Example on how to handle a specific error
+                    ErrorKind::InvalidSignature => "- Token signature is invalid".into(), // Example on how to handle a specific error
+                    _ => "- Some other errors".into(),
+                }
+            }
         };
 
-        if !is_logged_in {
-            let (request, _pl) = request.into_parts();
+        let user_oid = match user_oid {
+            Some(user_oid) => user_oid,
+            None => {
+                let (request, _pl) = request.into_parts();
 
-            let response = HttpResponse::Unauthorized()
-                .json(ErrorResPayload::new(
-                    "An error occurred!".to_string(),
-                    format!("Unauthorized {}", token_data),
-                ))
-                // constructed responses map to "right" body, early return res to client
-                .map_into_right_body();
+                let response = HttpResponse::Unauthorized()
+                    .json(ErrorResPayload::new(
+                        "An error occurred!".to_string(),
+                        format!("Unauthorized {}", token_data),
+                    ))
+                    // constructed responses map to "right" body, early return res to client
+                    .map_into_right_body();
 
-            return Box::pin(async { Ok(ServiceResponse::new(request, response)) });
-        }
+                return Box::pin(async { Ok(ServiceResponse::new(request, response)) });
+            }
+        };
 
+        tracing::Span::current().record("user_id", token_data.as_str());
         request.extensions_mut().insert(UserId(token_data));
+        request.extensions_mut().insert(UserObjectId(user_oid));
 
         let res = self.service.call(request);
 
@@ -123,3 +153,16 @@ where
         })
     }
 }
+
+// Label of the auth failures metric
+fn failure_reason(kind: &ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::InvalidToken => "invalid_token",
+        ErrorKind::InvalidSignature => "invalid_signature",
+        ErrorKind::ExpiredSignature => "expired_signature",
+        ErrorKind::ImmatureSignature => "immature_signature",
+        ErrorKind::InvalidAlgorithm => "invalid_algorithm",
+        ErrorKind::Base64(_) | ErrorKind::Json(_) | ErrorKind::Utf8(_) => "malformed_token",
+        _ => "other",
+    }
+}
@@ -0,0 +1,128 @@
+use actix_cors::Cors;
+
+use crate::config::CorsConfig;
+
+// Builds the CORS middleware from the validated CorsConfig.
+// Origins are either "*", an exact origin or a wildcard subdomain pattern like "https://*.example.com".
+pub fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(config.methods.iter().map(String::as_str))
+        .allowed_headers(config.headers.iter().map(String::as_str))
+        .expose_headers(config.expose_headers.iter().map(String::as_str))
+        .max_age(config.max_age);
+
+    if config.credentials {
+        cors = cors.supports_credentials();
+    }
+
+    if config.origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+        // Credentialed requests need the request's origin echoed back instead of "*"
+        if !config.credentials {
+            cors = cors.send_wildcard();
+        }
+        return cors;
+    }
+
+    let mut patterns = vec![];
+    for origin in &config.origins {
+        if origin.contains("*.") {
+            patterns.push(origin.to_lowercase());
+        } else {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    if !patterns.is_empty() {
+        cors = cors.allowed_origin_fn(move |origin, _| match origin.to_str() {
+            Ok(origin) => {
+                let origin = origin.to_lowercase();
+                patterns
+                    .iter()
+                    .any(|pattern| matches_origin_pattern(pattern, &origin))
+            }
+            Err(_) => false,
+        });
+    }
+
+    cors
+}
+
+// "https://*.example.com" matches "https://app.example.com" and "https://a.b.example.com",
+// but not "https://example.com" or "http://app.example.com"
+pub fn matches_origin_pattern(pattern: &str, origin: &str) -> bool {
+    let (prefix, suffix) = match pattern.split_once('*') {
+        Some(parts) => parts,
+        None => return pattern == origin,
+    };
+
+    if origin.len() <= prefix.len() + suffix.len()
+        || !origin.starts_with(prefix)
+        || !origin.ends_with(suffix)
+    {
+        return false;
+    }
+
+    origin[prefix.len()..origin.len() - suffix.len()]
+        .chars()
+        .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_patterns_match_only_the_same_origin() {
+        assert!(matches_origin_pattern(
+            "https://app.example.com",
+            "https://app.example.com"
+        ));
+        assert!(!matches_origin_pattern(
+            "https://app.example.com",
+            "https://app.example.com:8443"
+        ));
+        assert!(!matches_origin_pattern(
+            "https://app.example.com",
+            "http://app.example.com"
+        ));
+    }
+
+    #[test]
+    fn wildcards_match_any_subdomain_depth() {
+        let pattern = "https://*.example.com";
+        assert!(matches_origin_pattern(pattern, "https://app.example.com"));
+        assert!(matches_origin_pattern(pattern, "https://a.b.example.com"));
+        assert!(matches_origin_pattern(pattern, "https://app-1.example.com"));
+    }
+
+    #[test]
+    fn wildcards_need_a_subdomain() {
+        let pattern = "https://*.example.com";
+        assert!(!matches_origin_pattern(pattern, "https://example.com"));
+        assert!(!matches_origin_pattern(pattern, "https://.example.com"));
+    }
+
+    #[test]
+    fn wildcards_keep_the_scheme_port_and_domain() {
+        let pattern = "https://*.example.com";
+        assert!(!matches_origin_pattern(pattern, "http://app.example.com"));
+        assert!(!matches_origin_pattern(
+            pattern,
+            "https://app.example.com:8443"
+        ));
+        assert!(!matches_origin_pattern(pattern, "https://app.example.org"));
+        assert!(!matches_origin_pattern(
+            pattern,
+            "https://evil.com/.example.com"
+        ));
+        assert!(!matches_origin_pattern(
+            pattern,
+            "https://user@app.example.com"
+        ));
+        assert!(matches_origin_pattern(
+            "http://*.localhost:3000",
+            "http://app.localhost:3000"
+        ));
+    }
+}
@@ -0,0 +1,81 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+
+// Marks the responses of the unversioned route aliases as deprecated, see RFC 9745.
+// The Link header points clients to the same route under the successor version.
+pub struct DeprecatedAliasFactory {
+    pub deprecated_at: i64,
+    pub successor_prefix: &'static str,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for DeprecatedAliasFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeprecatedAliasMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        // The app fails to start rather than serving the aliases without the header
+        let deprecation = match HeaderValue::from_str(&format!("@{}", self.deprecated_at)) {
+            Ok(deprecation) => deprecation,
+            Err(_) => return ready(Err(())),
+        };
+        ready(Ok(DeprecatedAliasMiddleware {
+            service,
+            deprecation,
+            successor_prefix: self.successor_prefix,
+        }))
+    }
+}
+
+pub struct DeprecatedAliasMiddleware<S> {
+    service: S,
+    deprecation: HeaderValue,
+    successor_prefix: &'static str,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecatedAliasMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let link = HeaderValue::from_str(&format!(
+            "<{}{}>; rel=\"successor-version\"",
+            self.successor_prefix,
+            request.path()
+        ));
+        let deprecation = self.deprecation.clone();
+
+        let res = self.service.call(request);
+
+        Box::pin(async move {
+            let mut res = res.await?;
+            let headers = res.headers_mut();
+            headers.insert(HeaderName::from_static("deprecation"), deprecation);
+            if let Ok(link) = link {
+                headers.insert(HeaderName::from_static("link"), link);
+            }
+            Ok(res)
+        })
+    }
+}
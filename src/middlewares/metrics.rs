@@ -0,0 +1,79 @@
+use std::{
+    future::{ready, Ready},
+    time::Instant,
+};
+
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+
+use crate::services::metrics::Metrics;
+
+// Counts the requests and their latency by method, matched route and status
+pub struct RequestMetricsFactory {
+    pub metrics: Metrics,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetricsFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = request.method().to_string();
+        let metrics = self.metrics.clone();
+
+        let res = self.service.call(request);
+
+        Box::pin(async move {
+            let res = res.await?;
+            // The route is only known once the request was routed
+            let route = res
+                .request()
+                .match_pattern()
+                .unwrap_or("unmatched".to_string());
+            metrics.observe_request(
+                &method,
+                &route,
+                res.status().as_u16(),
+                started.elapsed().as_secs_f64(),
+            );
+            Ok(res)
+        })
+    }
+}
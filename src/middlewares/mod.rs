@@ -1 +1,5 @@
 pub mod auth;
+pub mod cors;
+pub mod deprecation;
+pub mod metrics;
+pub mod request_id;
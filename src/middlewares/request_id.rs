@@ -0,0 +1,123 @@
+use std::{
+    future::{ready, Ready},
+    time::Instant,
+};
+
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use rand::RngCore;
+use tracing::{field::Empty, Instrument};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longer IDs sent by clients are replaced, they end up in every log line of the request
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Runs every request in a "request" span carrying the request ID, which is taken from the
+// X-Request-Id header or generated, and echoed back in the response.
+// The auth middleware records the user ID on the span, see CheckLoginMiddleware.
+pub struct RequestTracingFactory;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracingFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+
+        let request_id = match request.headers().get(REQUEST_ID_HEADER) {
+            Some(header) => match header.to_str() {
+                Ok(id) if is_valid_request_id(id) => id.to_string(),
+                _ => new_request_id(),
+            },
+            None => new_request_id(),
+        };
+
+        // Only the matched route is logged, as paths may carry IDs and secrets
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %request.method(),
+            route = Empty,
+            status = Empty,
+            user_id = Empty,
+        );
+
+        let res = span.in_scope(|| self.service.call(request));
+
+        Box::pin(
+            async move {
+                let mut res = res.await?;
+
+                let span = tracing::Span::current();
+                let route = res
+                    .request()
+                    .match_pattern()
+                    .unwrap_or("unmatched".to_string());
+                span.record("route", route);
+                let status = res.status();
+                span.record("status", status.as_u16());
+                let latency_ms = started.elapsed().as_millis() as u64;
+                if status.is_server_error() {
+                    tracing::error!(latency_ms, "Request failed");
+                } else {
+                    tracing::info!(latency_ms, "Request completed");
+                }
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_' | '.' | ':'))
+}
+
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
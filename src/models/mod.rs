@@ -1 +1,3 @@
 pub mod mongo;
+pub mod report;
+pub mod schema;
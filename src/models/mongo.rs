@@ -1,22 +1,34 @@
+// The GraphQL derives generate resolvers named after the camelCase document fields
+#![allow(non_snake_case)]
+
+use async_graphql::SimpleObject;
 use mongodb::bson::{
     oid::ObjectId, /* serde_helpers::bson_datetime_as_rfc3339_string, */ DateTime,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize)]
+use super::schema::{DateTimeSchema, ObjectIdSchema};
+
+#[derive(Debug, Serialize, Deserialize, Clone, SimpleObject)]
+#[graphql(complex)]
 pub struct Day {
     #[serde(rename = "_id")]
     pub id: ObjectId,
+    #[graphql(skip)]
+    pub owner: ObjectId,
+    pub day: String,
+    #[graphql(name = "eventIds")]
     pub events: Vec<ObjectId>,
     // #[serde(with = "bson_datetime_as_rfc3339_string")]
     pub updatedAt: DateTime,
 }
 
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct EventDocument {
     #[serde(rename = "_id")]
+    #[schema(value_type = ObjectIdSchema)]
     pub id: ObjectId,
     pub title: String,
     pub date: f64,
@@ -26,22 +38,25 @@ pub struct EventDocument {
     pub bookingDetails: Option<Vec<BookingDetail>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub durationBooked: Option<f32>,
+    #[schema(value_type = ObjectIdSchema)]
+    #[graphql(name = "dayId")]
     pub day: ObjectId,
     pub duration: f32,
     // #[serde(with = "bson_datetime_as_rfc3339_string")]
+    #[schema(value_type = DateTimeSchema)]
     pub updatedAt: DateTime,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, SimpleObject)]
 pub struct Log {
     duration: f32,
     title: String,
 }
 
-#[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, ToSchema, SimpleObject)]
 pub struct BookingDetail {
     #[serde(rename = "_id")]
+    #[schema(value_type = ObjectIdSchema)]
     pub id: ObjectId,
     pub toDate: String,
     pub amount: f32,
@@ -56,3 +71,118 @@ impl BookingDetail {
         }
     }
 }
+
+// Teams are maintained by the project manager, like the days and events. The booking machine
+// only reads them, to let managers export the bookings of their team's members.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Team {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub members: Vec<ObjectId>,
+    pub managers: Vec<ObjectId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedToken {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub owner: ObjectId,
+    pub tokenHash: String,
+    pub createdAt: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub owner: ObjectId,
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+    pub active: bool,
+    pub createdAt: DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+// A single webhook call, queued until it is delivered or runs out of attempts
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id")]
+    #[schema(value_type = ObjectIdSchema)]
+    pub id: ObjectId,
+    #[schema(value_type = ObjectIdSchema)]
+    pub webhookId: ObjectId,
+    #[schema(value_type = ObjectIdSchema)]
+    pub owner: ObjectId,
+    pub event: String,
+    /// The exact JSON body that is signed and sent
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    #[schema(value_type = DateTimeSchema)]
+    pub nextAttemptAt: DateTime,
+    #[schema(value_type = Option<DateTimeSchema>)]
+    pub lastAttemptAt: Option<DateTime>,
+    pub lastStatusCode: Option<i32>,
+    pub lastError: Option<String>,
+    #[schema(value_type = DateTimeSchema)]
+    pub createdAt: DateTime,
+}
+
+// A booking domain event, written in the same transaction as the booking change.
+// The relay worker passes it on to every configured sink, the ID doubles as dedup ID.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub eventType: String,
+    pub payload: BookingEventPayload,
+    pub status: DeliveryStatus,
+    pub deliveredSinks: Vec<String>,
+    pub attempts: i32,
+    pub nextAttemptAt: DateTime,
+    pub lastError: Option<String>,
+    pub createdAt: DateTime,
+    pub relayedAt: Option<DateTime>,
+}
+
+// The booking change along with the event's resulting booking state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookingEventPayload {
+    pub userId: ObjectId,
+    pub eventId: ObjectId,
+    pub eventTitle: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bookingDetail: Option<BookingDetail>,
+    pub durationBooked: f32,
+    pub duration: f32,
+    pub booked: bool,
+}
+
+// A data migration that ran to completion, see handlers::migrations
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    #[serde(rename = "_id")]
+    pub version: i32,
+    pub name: String,
+    pub modified: i64,
+    pub appliedAt: DateTime,
+}
@@ -0,0 +1,67 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::schema::ObjectIdSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DayTotal {
+    pub day: String,
+    pub amount: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WeekTotal {
+    pub isoWeekYear: i32,
+    pub isoWeek: i32,
+    pub amount: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MonthTotal {
+    pub month: String,
+    pub amount: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EventTotal {
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+    pub title: String,
+    pub amount: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PartiallyBookedEvent {
+    #[serde(rename = "_id")]
+    #[schema(value_type = ObjectIdSchema)]
+    pub id: ObjectId,
+    pub title: String,
+    pub date: f64,
+    pub duration: f32,
+    pub durationBooked: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct BookingReport {
+    pub byDay: Vec<DayTotal>,
+    pub byWeek: Vec<WeekTotal>,
+    pub byMonth: Vec<MonthTotal>,
+    pub byEvent: Vec<EventTotal>,
+    pub partiallyBooked: Vec<PartiallyBookedEvent>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookingExportRow {
+    pub userId: ObjectId,
+    pub eventTitle: String,
+    pub eventDate: f64,
+    pub toDate: String,
+    pub amount: f32,
+    pub bookingId: ObjectId,
+}
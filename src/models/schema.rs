@@ -0,0 +1,25 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// MongoDB ObjectIds are serialized as extended JSON, e.g. {"$oid": "634e1f1c1f1c1f1c1f1c1f1c"}
+#[derive(Serialize, ToSchema)]
+#[schema(as = ObjectId)]
+pub struct ObjectIdSchema {
+    #[serde(rename = "$oid")]
+    pub oid: String,
+}
+
+// MongoDB DateTimes are serialized as extended JSON, e.g. {"$date": {"$numberLong": "1665000000000"}}
+#[derive(Serialize, ToSchema)]
+#[schema(as = DateTime)]
+pub struct DateTimeSchema {
+    #[serde(rename = "$date")]
+    pub date: NumberLongSchema,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(as = NumberLong)]
+pub struct NumberLongSchema {
+    #[serde(rename = "$numberLong")]
+    pub number_long: String,
+}
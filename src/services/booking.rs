@@ -0,0 +1,619 @@
+use std::fmt;
+
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::booking_rules::{BookingRuleViolation, BookingRules};
+use super::outbox::{booking_created_entries, outbox_entry, DomainEvent};
+use crate::api::routes_helpers::compare;
+use crate::handlers::mongo::MongoDB;
+use crate::models::mongo::{BookingDetail, EventDocument};
+use crate::models::schema::ObjectIdSchema;
+
+#[derive(Debug)]
+pub enum BookingError {
+    EventNotFound,
+    BookingNotFound,
+    RuleViolation(BookingRuleViolation),
+    CapacityExceeded {
+        amount: f32,
+        available: f32,
+    },
+    DailyCapExceeded {
+        amount: f32,
+        day: String,
+        available: f32,
+    },
+    // The Event's bookings changed while the booking was planned, nothing was written
+    Conflict,
+    Database(&'static str, mongodb::error::Error),
+}
+
+impl BookingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BookingError::EventNotFound => "EVENT_NOT_FOUND",
+            BookingError::BookingNotFound => "BOOKING_NOT_FOUND",
+            BookingError::RuleViolation(violation) => violation.code(),
+            BookingError::CapacityExceeded { .. } => "CAPACITY_EXCEEDED",
+            BookingError::DailyCapExceeded { .. } => "DAILY_CAP_EXCEEDED",
+            BookingError::Conflict => "BOOKING_CONFLICT",
+            BookingError::Database(..) => "DATABASE_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for BookingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookingError::EventNotFound => write!(f, "Event not found"),
+            BookingError::BookingNotFound => write!(f, "Booking detail not found!"),
+            BookingError::RuleViolation(violation) => write!(f, "{violation}"),
+            BookingError::CapacityExceeded { amount, available } => write!(
+                f,
+                "Unallowed amount: {amount}h, available booking hours: {available}h"
+            ),
+            BookingError::DailyCapExceeded {
+                amount,
+                day,
+                available,
+            } => write!(
+                f,
+                "Unallowed amount: {amount}h, available booking hours for {day}: {available}h"
+            ),
+            BookingError::Conflict => write!(
+                f,
+                "The event's bookings changed in the meantime, please try again"
+            ),
+            BookingError::Database(message, err) => write!(f, "{message} {err}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DayAction {
+    AddEvent,
+    RemoveEvent,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DayChange {
+    pub day: String,
+    pub action: DayAction,
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+}
+
+// The outcome of a booking, computed without writing to the database
+#[derive(Debug, Clone)]
+pub struct BookingPlan {
+    pub event: EventDocument,
+    // The owner of the event's Day
+    pub owner: ObjectId,
+    pub booking_detail: BookingDetail,
+    pub duration_booked: f32,
+    pub fully_booked: bool,
+    pub day_change: Option<DayChange>,
+}
+
+// The outcome of a Booking Detail deletion, computed without writing to the database
+#[derive(Debug)]
+pub struct DeletionPlan {
+    pub event: EventDocument,
+    pub owner: ObjectId,
+    pub booking_detail: BookingDetail,
+    pub duration_booked: f32,
+    pub last_for_day: bool,
+    pub day_change: Option<DayChange>,
+}
+
+// The outcome of changing the day or amount of a Booking Detail, computed without writing to the database
+#[derive(Debug)]
+pub struct UpdatePlan {
+    pub event: EventDocument,
+    pub owner: ObjectId,
+    pub booking_detail: BookingDetail,
+    pub duration_booked: f32,
+    pub fully_booked: bool,
+    pub was_fully_booked: bool,
+    pub day_changes: Vec<DayChange>,
+}
+
+impl BookingPlan {
+    pub fn available_hours(&self) -> f32 {
+        self.event.duration - self.duration_booked
+    }
+}
+
+impl DeletionPlan {
+    pub fn available_hours(&self) -> f32 {
+        self.event.duration - self.duration_booked
+    }
+}
+
+impl UpdatePlan {
+    pub fn available_hours(&self) -> f32 {
+        self.event.duration - self.duration_booked
+    }
+}
+
+// Fetches an Event of the user along with the owner of its Day. Events are owned through
+// their Day, the ones of other users are reported as not found.
+pub async fn find_owned_event(
+    db: &MongoDB,
+    user_id: &str,
+    event_id: &str,
+) -> Result<(EventDocument, ObjectId), BookingError> {
+    let event = match db.find_event_by_id(event_id).await {
+        Ok(Some(event_doc)) => event_doc,
+        Ok(None) => return Err(BookingError::EventNotFound),
+        Err(err) => {
+            return Err(BookingError::Database(
+                "An error occurred while fetching the event!",
+                err,
+            ))
+        }
+    };
+
+    match event_owner(db, &event).await? {
+        Some(owner) if owner.to_hex() == user_id => Ok((event, owner)),
+        _ => Err(BookingError::EventNotFound),
+    }
+}
+
+// Fetches the Event holding a Booking Detail of the user, see find_owned_event
+pub async fn find_owned_booking(
+    db: &MongoDB,
+    user_id: &str,
+    booking_id_str: &str,
+) -> Result<(EventDocument, ObjectId), BookingError> {
+    let event = match db.find_bookingdetail_by_id(booking_id_str).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err(BookingError::BookingNotFound),
+        Err(err) => return Err(BookingError::Database("An error ocurred!", err)),
+    };
+
+    match event_owner(db, &event).await? {
+        Some(owner) if owner.to_hex() == user_id => Ok((event, owner)),
+        _ => Err(BookingError::BookingNotFound),
+    }
+}
+
+async fn event_owner(
+    db: &MongoDB,
+    event: &EventDocument,
+) -> Result<Option<ObjectId>, BookingError> {
+    match db.find_day_by_id(event.day).await {
+        Ok(day) => Ok(day.map(|day| day.owner)),
+        Err(err) => Err(BookingError::Database(
+            "An error occurred while fetching the day!",
+            err,
+        )),
+    }
+}
+
+// Runs all validation, capacity and day membership checks of a booking.
+// Expects an already validated payload, see BookingPayload::validate.
+pub async fn plan_booking(
+    db: &MongoDB,
+    rules: &BookingRules,
+    user_id: &str,
+    event_id: &str,
+    day: &str,
+    amount: f32,
+) -> Result<BookingPlan, BookingError> {
+    let (event, owner) = find_owned_event(db, user_id, event_id).await?;
+
+    let daily_cap = match rules.daily_cap_for(user_id) {
+        Some(daily_cap) => match db.find_booked_amount_for_day(user_id, day).await {
+            Ok(booked_for_day) => Some(DailyCap {
+                cap: daily_cap,
+                booked: booked_for_day,
+            }),
+            Err(err) => {
+                return Err(BookingError::Database(
+                    "An error occurred while fetching the booked hours!",
+                    err,
+                ))
+            }
+        },
+        None => None,
+    };
+
+    plan_booking_on_event(event, owner, rules, daily_cap, day, amount)
+}
+
+// The user's daily cap and the hours already booked onto the booking day
+#[derive(Debug, Clone, Copy)]
+pub struct DailyCap {
+    pub cap: f32,
+    pub booked: f32,
+}
+
+// Plans a booking against an already fetched event, without any database access
+pub fn plan_booking_on_event(
+    event: EventDocument,
+    owner: ObjectId,
+    rules: &BookingRules,
+    daily_cap: Option<DailyCap>,
+    day: &str,
+    amount: f32,
+) -> Result<BookingPlan, BookingError> {
+    // Construct the new BookingDetail object
+    let booking_detail = BookingDetail::new(day.to_string(), amount);
+
+    plan_booking_detail_on_event(event, owner, rules, daily_cap, booking_detail)
+}
+
+fn plan_booking_detail_on_event(
+    mut event: EventDocument,
+    owner: ObjectId,
+    rules: &BookingRules,
+    daily_cap: Option<DailyCap>,
+    booking_detail: BookingDetail,
+) -> Result<BookingPlan, BookingError> {
+    let day = booking_detail.toDate.as_str();
+    let amount = booking_detail.amount;
+
+    // Check the booking day against the event date window and the business calendar
+    rules
+        .check(event.date, day)
+        .map_err(BookingError::RuleViolation)?;
+
+    // Set the initial duration booked to the submitted amount
+    let mut duration_booked = booking_detail.amount;
+
+    if let Some(deets) = &event.bookingDetails {
+        duration_booked += deets
+            .iter()
+            .fold(0.0, |acc, booking_detail| acc + booking_detail.amount);
+    }
+
+    // Do not allow more booking time than worked time
+    if duration_booked > event.duration {
+        return Err(BookingError::CapacityExceeded {
+            amount,
+            available: event.duration - (duration_booked - amount),
+        });
+    }
+
+    // Do not allow more booking time per day than the user's daily cap
+    if let Some(DailyCap { cap, booked }) = daily_cap {
+        if booked + amount > cap {
+            return Err(BookingError::DailyCapExceeded {
+                amount,
+                day: day.to_string(),
+                available: (cap - booked).max(0.0),
+            });
+        }
+    }
+
+    let fully_booked = duration_booked == event.duration;
+
+    // If booking is made for a different day, add the event to that day
+    let day_change = if !compare(event.date, &booking_detail.toDate) {
+        Some(DayChange {
+            day: day.to_string(),
+            action: DayAction::AddEvent,
+            eventId: event.id,
+        })
+    } else {
+        None
+    };
+
+    // Apply the changes to the fetched event, so that it reflects the would-be state
+    event
+        .bookingDetails
+        .get_or_insert_with(Vec::new)
+        .push(booking_detail.clone());
+    event.durationBooked = Some(duration_booked);
+    event.booked = fully_booked;
+    event.updatedAt = DateTime::now();
+
+    Ok(BookingPlan {
+        event,
+        owner,
+        booking_detail,
+        duration_booked,
+        fully_booked,
+        day_change,
+    })
+}
+
+pub async fn apply_booking(
+    db: &MongoDB,
+    user_id: &str,
+    plan: BookingPlan,
+) -> Result<Option<EventDocument>, BookingError> {
+    if let Some(day_change) = &plan.day_change {
+        if let Err(err) = db
+            .add_event_to_day(user_id, &day_change.day, plan.event.id)
+            .await
+        {
+            return Err(BookingError::Database(
+                "An error occurred while updating the day!",
+                err,
+            ));
+        }
+    }
+
+    // The booked amount is at least 0.25h, so the event was not fully booked before
+    let outbox = booking_created_entries(
+        plan.owner,
+        &plan.event,
+        &plan.booking_detail,
+        plan.fully_booked,
+    );
+
+    // The planned event already holds the new booking detail
+    let read_booking_count = plan
+        .event
+        .bookingDetails
+        .as_ref()
+        .map_or(0, |booking_details| booking_details.len() - 1);
+
+    match db
+        .add_bookingdetail_to_event(
+            plan.event.id,
+            read_booking_count,
+            plan.booking_detail,
+            plan.duration_booked,
+            plan.fully_booked,
+            &outbox,
+        )
+        .await
+    {
+        Ok(Some(event)) => Ok(Some(event)),
+        // Another booking changed the event since it was read, its booking state is stale
+        Ok(None) => Err(BookingError::Conflict),
+        Err(err) => Err(BookingError::Database(
+            "An error occurred while updating the event!",
+            err,
+        )),
+    }
+}
+
+// Expects an already validated payload, see DeleteBookingPayload::validate.
+pub async fn plan_deletion(
+    db: &MongoDB,
+    user_id: &str,
+    booking_id_str: &str,
+) -> Result<DeletionPlan, BookingError> {
+    let (mut event, owner) = find_owned_booking(db, user_id, booking_id_str).await?;
+
+    // Events saved before durationBooked was tracked only have their Booking Details
+    let duration_booked = match &event.durationBooked {
+        Some(duration_booked) => *duration_booked,
+        None => event
+            .bookingDetails
+            .iter()
+            .flatten()
+            .fold(0.0, |acc, booking_detail| acc + booking_detail.amount),
+    };
+
+    let booking_details = match &mut event.bookingDetails {
+        Some(booking_details) => booking_details,
+        None => return Err(BookingError::BookingNotFound),
+    };
+
+    let booking_id = ObjectId::parse_str(booking_id_str).unwrap();
+    let booking_detail = match booking_details
+        .iter()
+        .position(|detail| detail.id == booking_id)
+    {
+        Some(position) => booking_details.remove(position),
+        None => return Err(BookingError::BookingNotFound),
+    };
+
+    let updated_duration_booked = duration_booked - booking_detail.amount;
+
+    // Check if other details with the same destination date exist
+    let last_for_day = !booking_details
+        .iter()
+        .any(|detail| detail.toDate == booking_detail.toDate);
+
+    // Check if the Event and Booking Detail belong to the same destination Day and if there are multiple Booking Details for the destination Day
+    // Removes the event from the destination Day
+    let day_change = if !compare(event.date, &booking_detail.toDate) && last_for_day {
+        Some(DayChange {
+            day: booking_detail.toDate.clone(),
+            action: DayAction::RemoveEvent,
+            eventId: event.id,
+        })
+    } else {
+        None
+    };
+
+    // Apply the changes to the fetched event, so that it reflects the would-be state
+    event.durationBooked = Some(updated_duration_booked);
+    event.booked = false;
+    event.updatedAt = DateTime::now();
+
+    Ok(DeletionPlan {
+        event,
+        owner,
+        booking_detail,
+        duration_booked: updated_duration_booked,
+        last_for_day,
+        day_change,
+    })
+}
+
+pub async fn apply_deletion(
+    db: &MongoDB,
+    user_id: &str,
+    plan: DeletionPlan,
+) -> Result<EventDocument, BookingError> {
+    let outbox = [outbox_entry(
+        DomainEvent::BookingDeleted,
+        plan.owner,
+        &plan.event,
+        Some(&plan.booking_detail),
+    )];
+
+    // Delete the Booking Detail from the Event
+    let updated_event = match db
+        .remove_bookingdetail_from_event(
+            plan.event.id,
+            &plan.booking_detail,
+            plan.duration_booked,
+            &outbox,
+        )
+        .await
+    {
+        Ok(Some(event)) => event,
+        // The Event was deleted meanwhile
+        Ok(None) => return Err(BookingError::EventNotFound),
+        Err(err) => return Err(BookingError::Database("An error ocurred!", err)),
+    };
+
+    if let Some(day_change) = &plan.day_change {
+        if let Err(err) = db
+            .remove_event_from_day(user_id, &day_change.day, plan.event.id)
+            .await
+        {
+            return Err(BookingError::Database("An error ocurred", err));
+        }
+    }
+
+    Ok(updated_event)
+}
+
+// Plans moving a Booking Detail to another day and/or changing its amount, keeping its ID.
+// The Booking Detail is taken out of the event first, so its own hours don't count against the new amount.
+pub async fn plan_update(
+    db: &MongoDB,
+    rules: &BookingRules,
+    user_id: &str,
+    booking_id_str: &str,
+    day: Option<&str>,
+    amount: Option<f32>,
+) -> Result<UpdatePlan, BookingError> {
+    let deletion = plan_deletion(db, user_id, booking_id_str).await?;
+    let previous = deletion.booking_detail;
+    let was_fully_booked = deletion.duration_booked + previous.amount == deletion.event.duration;
+
+    let booking_detail = BookingDetail {
+        id: previous.id,
+        toDate: day.unwrap_or(&previous.toDate).to_string(),
+        amount: amount.unwrap_or(previous.amount),
+    };
+    let same_day = booking_detail.toDate == previous.toDate;
+
+    let daily_cap = match rules.daily_cap_for(user_id) {
+        Some(daily_cap) => match db
+            .find_booked_amount_for_day(user_id, &booking_detail.toDate)
+            .await
+        {
+            Ok(booked_for_day) => Some(DailyCap {
+                cap: daily_cap,
+                booked: if same_day {
+                    booked_for_day - previous.amount
+                } else {
+                    booked_for_day
+                },
+            }),
+            Err(err) => {
+                return Err(BookingError::Database(
+                    "An error occurred while fetching the booked hours!",
+                    err,
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let booking = plan_booking_detail_on_event(
+        deletion.event,
+        deletion.owner,
+        rules,
+        daily_cap,
+        booking_detail,
+    )?;
+
+    // The day memberships only change when the Booking Detail moves to another day
+    let day_changes = if same_day {
+        vec![]
+    } else {
+        deletion
+            .day_change
+            .into_iter()
+            .chain(booking.day_change)
+            .collect()
+    };
+
+    Ok(UpdatePlan {
+        event: booking.event,
+        owner: booking.owner,
+        booking_detail: booking.booking_detail,
+        duration_booked: booking.duration_booked,
+        fully_booked: booking.fully_booked,
+        was_fully_booked,
+        day_changes,
+    })
+}
+
+pub async fn apply_update(
+    db: &MongoDB,
+    user_id: &str,
+    plan: UpdatePlan,
+) -> Result<EventDocument, BookingError> {
+    let mut outbox = vec![outbox_entry(
+        DomainEvent::BookingUpdated,
+        plan.owner,
+        &plan.event,
+        Some(&plan.booking_detail),
+    )];
+    if plan.fully_booked && !plan.was_fully_booked {
+        outbox.push(outbox_entry(
+            DomainEvent::EventFullyBooked,
+            plan.owner,
+            &plan.event,
+            None,
+        ));
+    }
+
+    let updated_event = match db
+        .replace_bookingdetail_in_event(
+            plan.event.id,
+            &plan.booking_detail,
+            plan.duration_booked,
+            plan.fully_booked,
+            &outbox,
+        )
+        .await
+    {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err(BookingError::BookingNotFound),
+        Err(err) => {
+            return Err(BookingError::Database(
+                "An error occurred while updating the event!",
+                err,
+            ))
+        }
+    };
+
+    for day_change in &plan.day_changes {
+        let updated_day = match day_change.action {
+            DayAction::AddEvent => {
+                db.add_event_to_day(user_id, &day_change.day, plan.event.id)
+                    .await
+            }
+            DayAction::RemoveEvent => {
+                db.remove_event_from_day(user_id, &day_change.day, plan.event.id)
+                    .await
+            }
+        };
+        if let Err(err) = updated_day {
+            return Err(BookingError::Database(
+                "An error occurred while updating the day!",
+                err,
+            ));
+        }
+    }
+
+    Ok(updated_event)
+}
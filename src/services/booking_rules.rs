@@ -0,0 +1,232 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc, Weekday};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookingRuleViolation {
+    InvalidDay,
+    BeforeEventWindow { max_days: i64 },
+    AfterEventWindow { max_days: i64 },
+    InFuture,
+    Weekend,
+    Holiday,
+}
+
+impl BookingRuleViolation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BookingRuleViolation::InvalidDay => "INVALID_DAY",
+            BookingRuleViolation::BeforeEventWindow { .. } => "DAY_BEFORE_EVENT_WINDOW",
+            BookingRuleViolation::AfterEventWindow { .. } => "DAY_AFTER_EVENT_WINDOW",
+            BookingRuleViolation::InFuture => "DAY_IN_FUTURE",
+            BookingRuleViolation::Weekend => "DAY_IS_WEEKEND",
+            BookingRuleViolation::Holiday => "DAY_IS_HOLIDAY",
+        }
+    }
+}
+
+impl fmt::Display for BookingRuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookingRuleViolation::InvalidDay => {
+                write!(f, "Booking day must use the format YYYY-MM-DD")
+            }
+            BookingRuleViolation::BeforeEventWindow { max_days } => write!(
+                f,
+                "Booking day can be at most {max_days} day(s) before the event date"
+            ),
+            BookingRuleViolation::AfterEventWindow { max_days } => write!(
+                f,
+                "Booking day can be at most {max_days} day(s) after the event date"
+            ),
+            BookingRuleViolation::InFuture => write!(f, "Booking day cannot be in the future"),
+            BookingRuleViolation::Weekend => write!(f, "Booking day cannot be on a weekend"),
+            BookingRuleViolation::Holiday => write!(f, "Booking day cannot be on a holiday"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BookingRules {
+    pub max_days_before: Option<i64>,
+    pub max_days_after: Option<i64>,
+    pub allow_future: bool,
+    pub block_weekends: bool,
+    pub holidays: HashSet<NaiveDate>,
+    pub daily_cap: Option<f32>,
+    pub daily_cap_overrides: HashMap<String, f32>,
+}
+
+impl BookingRules {
+    // Per-user overrides take precedence over the global daily cap
+    pub fn daily_cap_for(&self, user_id: &str) -> Option<f32> {
+        self.daily_cap_overrides
+            .get(user_id)
+            .copied()
+            .or(self.daily_cap)
+    }
+
+    // Checks the booking day against the event date window and the business calendar.
+    // Expects an already validated "YYYY-MM-DD" day, see BookingPayload::validate.
+    pub fn check(&self, event_date: f64, day: &str) -> Result<(), BookingRuleViolation> {
+        let day = match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+            Ok(day) => day,
+            Err(_) => return Err(BookingRuleViolation::InvalidDay),
+        };
+        let event_day = DateTime::from_timestamp(event_date as i64 / 1000, 0)
+            .expect("Invalid Event date format")
+            .date_naive();
+
+        let offset = (day - event_day).num_days();
+
+        if let Some(max_days) = self.max_days_before {
+            if offset < -max_days {
+                return Err(BookingRuleViolation::BeforeEventWindow { max_days });
+            }
+        }
+
+        if let Some(max_days) = self.max_days_after {
+            if offset > max_days {
+                return Err(BookingRuleViolation::AfterEventWindow { max_days });
+            }
+        }
+
+        if !self.allow_future && day > Utc::now().naive_utc().date() {
+            return Err(BookingRuleViolation::InFuture);
+        }
+
+        if self.block_weekends && matches!(day.weekday(), Weekday::Sat | Weekday::Sun) {
+            return Err(BookingRuleViolation::Weekend);
+        }
+
+        if self.holidays.contains(&day) {
+            return Err(BookingRuleViolation::Holiday);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    // Milliseconds since the epoch, like the event dates stored in Mongo
+    fn event_date(day: &str) -> f64 {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis() as f64
+    }
+
+    fn rules() -> BookingRules {
+        BookingRules {
+            allow_future: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_any_valid_day_without_rules() {
+        assert_eq!(
+            rules().check(event_date("2024-03-04"), "2023-01-01"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_an_invalid_day() {
+        assert_eq!(
+            rules().check(event_date("2024-03-04"), "2024-13-01"),
+            Err(BookingRuleViolation::InvalidDay)
+        );
+    }
+
+    #[test]
+    fn enforces_the_event_window_inclusively() {
+        let rules = BookingRules {
+            max_days_before: Some(2),
+            max_days_after: Some(1),
+            ..rules()
+        };
+        let event = event_date("2024-03-06");
+        assert_eq!(rules.check(event, "2024-03-04"), Ok(()));
+        assert_eq!(rules.check(event, "2024-03-07"), Ok(()));
+        assert_eq!(
+            rules.check(event, "2024-03-03"),
+            Err(BookingRuleViolation::BeforeEventWindow { max_days: 2 })
+        );
+        assert_eq!(
+            rules.check(event, "2024-03-08"),
+            Err(BookingRuleViolation::AfterEventWindow { max_days: 1 })
+        );
+    }
+
+    #[test]
+    fn rejects_future_days_unless_allowed() {
+        let tomorrow = (Utc::now().date_naive() + Duration::days(1))
+            .format("%Y-%m-%d")
+            .to_string();
+        let today = event_date(&Utc::now().date_naive().format("%Y-%m-%d").to_string());
+        let strict = BookingRules {
+            allow_future: false,
+            ..rules()
+        };
+        assert_eq!(
+            strict.check(today, &tomorrow),
+            Err(BookingRuleViolation::InFuture)
+        );
+        assert_eq!(rules().check(today, &tomorrow), Ok(()));
+    }
+
+    #[test]
+    fn blocks_weekends_when_configured() {
+        let rules = BookingRules {
+            block_weekends: true,
+            ..rules()
+        };
+        let event = event_date("2024-03-04");
+        // 2024-03-09 is a Saturday, 2024-03-10 a Sunday
+        assert_eq!(
+            rules.check(event, "2024-03-09"),
+            Err(BookingRuleViolation::Weekend)
+        );
+        assert_eq!(
+            rules.check(event, "2024-03-10"),
+            Err(BookingRuleViolation::Weekend)
+        );
+        assert_eq!(rules.check(event, "2024-03-08"), Ok(()));
+    }
+
+    #[test]
+    fn blocks_holidays() {
+        let rules = BookingRules {
+            holidays: HashSet::from([NaiveDate::from_ymd_opt(2024, 12, 25).unwrap()]),
+            ..rules()
+        };
+        let event = event_date("2024-12-24");
+        assert_eq!(
+            rules.check(event, "2024-12-25"),
+            Err(BookingRuleViolation::Holiday)
+        );
+        assert_eq!(rules.check(event, "2024-12-24"), Ok(()));
+    }
+
+    #[test]
+    fn user_overrides_take_precedence_over_the_daily_cap() {
+        let rules = BookingRules {
+            daily_cap: Some(8.0),
+            daily_cap_overrides: HashMap::from([("634e1f1c1f1c1f1c1f1c1f1c".to_string(), 6.0)]),
+            ..rules()
+        };
+        assert_eq!(rules.daily_cap_for("634e1f1c1f1c1f1c1f1c1f1c"), Some(6.0));
+        assert_eq!(rules.daily_cap_for("634e1f1c1f1c1f1c1f1c1f1d"), Some(8.0));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::handlers::mongo::MongoDB;
+use crate::models::mongo::{Day, EventDocument};
+use crate::models::schema::ObjectIdSchema;
+
+// Booked amounts are f32 quarter hours, sums are compared with some leeway
+const EPSILON: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InconsistencyKind {
+    // A Day lists an Event that doesn't exist
+    DanglingEventReference,
+    // A Day lists an Event that is neither on that day nor booked onto it
+    StaleDayReference,
+    // An Event is on or booked onto a Day that doesn't list it
+    MissingDayReference,
+    // An Event is booked onto a day the owner has no Day for, can't be repaired
+    MissingDay,
+    // An Event's Day doesn't exist, can't be repaired
+    OrphanedEvent,
+    // durationBooked isn't the sum of the Booking Details' amounts
+    DurationMismatch,
+    // booked doesn't match whether the Booking Details cover the duration
+    StaleBookedFlag,
+}
+
+impl InconsistencyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InconsistencyKind::DanglingEventReference => "danglingEventReference",
+            InconsistencyKind::StaleDayReference => "staleDayReference",
+            InconsistencyKind::MissingDayReference => "missingDayReference",
+            InconsistencyKind::MissingDay => "missingDay",
+            InconsistencyKind::OrphanedEvent => "orphanedEvent",
+            InconsistencyKind::DurationMismatch => "durationMismatch",
+            InconsistencyKind::StaleBookedFlag => "staleBookedFlag",
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Inconsistency {
+    pub kind: InconsistencyKind,
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<ObjectIdSchema>)]
+    pub dayId: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<String>,
+    pub detail: String,
+    pub fixed: bool,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConsistencyReport {
+    pub fix: bool,
+    pub daysScanned: usize,
+    pub eventsScanned: usize,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl ConsistencyReport {
+    // Whether anything is left to repair, by hand for the kinds that can't be fixed
+    pub fn has_unfixed(&self) -> bool {
+        self.inconsistencies
+            .iter()
+            .any(|inconsistency| !inconsistency.fixed)
+    }
+}
+
+// The owner and date of a Day, all the scan keeps of a Day in memory
+struct DayKey {
+    owner: ObjectId,
+    day: String,
+}
+
+// Scans the days and events collections for the drift left behind by the booking changes,
+// which update both separately. With fix, every repairable inconsistency is repaired on the
+// way. Bookings made while the scan runs may show up as false positives, the repairs are
+// skipped for the documents changed since they were read.
+//
+// Both collections are streamed. The days are read twice, first for their owners and dates,
+// then for their Event references once the expected ones are known.
+pub async fn check_consistency(
+    db: &MongoDB,
+    fix: bool,
+) -> Result<ConsistencyReport, mongodb::error::Error> {
+    let mut days: HashMap<ObjectId, DayKey> = HashMap::new();
+    let mut day_ids: HashMap<(ObjectId, String), ObjectId> = HashMap::new();
+    let mut day_cursor = db.find_all_days().await?;
+    while let Some(day) = day_cursor.try_next().await? {
+        day_ids.insert((day.owner, day.day.clone()), day.id);
+        days.insert(
+            day.id,
+            DayKey {
+                owner: day.owner,
+                day: day.day,
+            },
+        );
+    }
+
+    let mut inconsistencies: Vec<Inconsistency> = vec![];
+    let mut event_ids: HashSet<ObjectId> = HashSet::new();
+    // The Events that are expected to be listed in each Day
+    let mut expected_references: HashMap<ObjectId, HashSet<ObjectId>> = HashMap::new();
+
+    let mut events = db.find_all_events().await?;
+    while let Some(event) = events.try_next().await? {
+        event_ids.insert(event.id);
+
+        match days.get(&event.day) {
+            Some(home) => {
+                // The Event's own Day, followed by the Days it's booked onto
+                let mut event_days: Vec<ObjectId> = vec![event.day];
+                let mut missing_days: HashSet<&str> = HashSet::new();
+                for detail in event.bookingDetails.iter().flatten() {
+                    if detail.toDate == home.day {
+                        continue;
+                    }
+                    match day_ids.get(&(home.owner, detail.toDate.clone())) {
+                        Some(day_id) => event_days.push(*day_id),
+                        None if missing_days.insert(&detail.toDate) => {
+                            inconsistencies.push(Inconsistency {
+                                kind: InconsistencyKind::MissingDay,
+                                eventId: event.id,
+                                dayId: None,
+                                day: Some(detail.toDate.clone()),
+                                detail: format!(
+                                    "The owner {} has no Day {}",
+                                    home.owner, detail.toDate
+                                ),
+                                fixed: false,
+                            })
+                        }
+                        None => {}
+                    }
+                }
+
+                for day_id in event_days {
+                    expected_references
+                        .entry(day_id)
+                        .or_default()
+                        .insert(event.id);
+                }
+            }
+            None => inconsistencies.push(Inconsistency {
+                kind: InconsistencyKind::OrphanedEvent,
+                eventId: event.id,
+                dayId: Some(event.day),
+                day: None,
+                detail: "The Event's Day doesn't exist".to_string(),
+                fixed: false,
+            }),
+        }
+
+        inconsistencies.extend(check_booking_state(db, &event, fix).await?);
+    }
+
+    let mut day_cursor = db.find_all_days().await?;
+    while let Some(day) = day_cursor.try_next().await? {
+        let expected = expected_references.remove(&day.id).unwrap_or_default();
+
+        for event_id in &expected {
+            if day.events.contains(event_id) {
+                continue;
+            }
+            // Adding a reference is idempotent, an Event listed twice can't come of it
+            if fix {
+                db.add_event_to_day_by_id(day.id, *event_id).await?;
+            }
+            inconsistencies.push(Inconsistency {
+                kind: InconsistencyKind::MissingDayReference,
+                eventId: *event_id,
+                dayId: Some(day.id),
+                day: Some(day.day.clone()),
+                detail: "The Day doesn't list the Event".to_string(),
+                fixed: fix,
+            });
+        }
+
+        for event_id in &day.events {
+            let (kind, detail) = if !event_ids.contains(event_id) {
+                (
+                    InconsistencyKind::DanglingEventReference,
+                    "The Event doesn't exist",
+                )
+            } else if !expected.contains(event_id) {
+                (
+                    InconsistencyKind::StaleDayReference,
+                    "The Event is neither on nor booked onto the Day",
+                )
+            } else {
+                continue;
+            };
+            let fixed = fix && is_still_stale(db, &day, *event_id).await?;
+            if fixed {
+                db.remove_event_from_day_by_id(day.id, *event_id).await?;
+            }
+            inconsistencies.push(Inconsistency {
+                kind,
+                eventId: *event_id,
+                dayId: Some(day.id),
+                day: Some(day.day.clone()),
+                detail: detail.to_string(),
+                fixed,
+            });
+        }
+    }
+
+    Ok(ConsistencyReport {
+        fix,
+        daysScanned: days.len(),
+        eventsScanned: event_ids.len(),
+        inconsistencies,
+    })
+}
+
+// Re-reads the Event before its reference is pulled from the Day, as it may have been
+// created or booked onto the Day since the scan read it
+async fn is_still_stale(
+    db: &MongoDB,
+    day: &Day,
+    event_id: ObjectId,
+) -> Result<bool, mongodb::error::Error> {
+    match db.find_event_by_id(&event_id.to_hex()).await? {
+        Some(event) => Ok(event.day != day.id
+            && !event
+                .bookingDetails
+                .iter()
+                .flatten()
+                .any(|detail| detail.toDate == day.day)),
+        None => Ok(true),
+    }
+}
+
+// Compares durationBooked and booked with the Booking Details, which are the source of truth
+pub async fn check_booking_state(
+    db: &MongoDB,
+    event: &EventDocument,
+    fix: bool,
+) -> Result<Vec<Inconsistency>, mongodb::error::Error> {
+    let booked_sum: f32 = event
+        .bookingDetails
+        .iter()
+        .flatten()
+        .map(|detail| detail.amount)
+        .sum();
+    let duration_booked = event.durationBooked.unwrap_or(0.0);
+    let fully_booked = booked_sum >= event.duration - EPSILON;
+
+    let mut inconsistencies: Vec<Inconsistency> = vec![];
+    if (booked_sum - duration_booked).abs() > EPSILON {
+        inconsistencies.push(Inconsistency {
+            kind: InconsistencyKind::DurationMismatch,
+            eventId: event.id,
+            dayId: None,
+            day: None,
+            detail: format!(
+                "durationBooked is {duration_booked}h, the Booking Details add up to {booked_sum}h"
+            ),
+            fixed: fix,
+        });
+    }
+    if event.booked != fully_booked {
+        inconsistencies.push(Inconsistency {
+            kind: InconsistencyKind::StaleBookedFlag,
+            eventId: event.id,
+            dayId: None,
+            day: None,
+            detail: format!(
+                "booked is {}, {booked_sum}h of {}h are booked",
+                event.booked, event.duration
+            ),
+            fixed: fix,
+        });
+    }
+
+    if fix && !inconsistencies.is_empty() {
+        // Left alone if the Event changed since it was read, the next check picks it up again
+        let result = db
+            .set_event_booking_state(event, booked_sum, fully_booked)
+            .await?;
+        if result.matched_count == 0 {
+            for inconsistency in inconsistencies.iter_mut() {
+                inconsistency.fixed = false;
+                inconsistency
+                    .detail
+                    .push_str(", not repaired as the Event changed meanwhile");
+            }
+        }
+    }
+    Ok(inconsistencies)
+}
@@ -0,0 +1,354 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+// Hours are distributed in quarter-hour units, the smallest bookable amount
+const UNITS_PER_HOUR: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistributionStrategy {
+    Even,
+    FillToDailyCap,
+    Weekdays,
+}
+
+impl FromStr for DistributionStrategy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "even" => Ok(DistributionStrategy::Even),
+            "fillToDailyCap" => Ok(DistributionStrategy::FillToDailyCap),
+            "weekdays" => Ok(DistributionStrategy::Weekdays),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DistributionError {
+    NothingToDistribute,
+    NotQuarterHours { remaining: f32 },
+    NoEligibleDays,
+    NoDailyCap,
+    InsufficientCapacity { remaining: f32, capacity: f32 },
+    DailyCapExceeded { day: String },
+}
+
+impl DistributionError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DistributionError::NothingToDistribute => "NOTHING_TO_DISTRIBUTE",
+            DistributionError::NotQuarterHours { .. } => "REMAINING_NOT_QUARTER_HOURS",
+            DistributionError::NoEligibleDays => "NO_ELIGIBLE_DAYS",
+            DistributionError::NoDailyCap => "NO_DAILY_CAP",
+            DistributionError::InsufficientCapacity { .. } => "INSUFFICIENT_DAILY_CAPACITY",
+            DistributionError::DailyCapExceeded { .. } => "DAILY_CAP_EXCEEDED",
+        }
+    }
+}
+
+impl fmt::Display for DistributionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DistributionError::NothingToDistribute => {
+                write!(f, "The event has no remaining hours to book")
+            }
+            DistributionError::NotQuarterHours { remaining } => write!(
+                f,
+                "Remaining {remaining}h cannot be split into 0.25h increments"
+            ),
+            DistributionError::NoEligibleDays => {
+                write!(f, "No bookable days in the requested date range")
+            }
+            DistributionError::NoDailyCap => write!(
+                f,
+                "The fillToDailyCap strategy requires a daily booking cap"
+            ),
+            DistributionError::InsufficientCapacity {
+                remaining,
+                capacity,
+            } => write!(
+                f,
+                "Remaining {remaining}h exceed the available {capacity}h in the requested date range"
+            ),
+            DistributionError::DailyCapExceeded { day } => {
+                write!(f, "Distribution exceeds the daily booking cap on {day}")
+            }
+        }
+    }
+}
+
+// A candidate day with the hours still available under the daily cap, if any
+#[derive(Debug, Clone)]
+pub struct DayCapacity {
+    pub day: NaiveDate,
+    pub available: Option<f32>,
+}
+
+// Returns the days in the inclusive range which the strategy may book onto
+pub fn candidate_days(
+    from: NaiveDate,
+    to: NaiveDate,
+    strategy: DistributionStrategy,
+) -> Vec<NaiveDate> {
+    let mut days = vec![];
+    let mut day = from;
+    while day <= to {
+        let is_weekend = matches!(day.weekday(), Weekday::Sat | Weekday::Sun);
+        if strategy != DistributionStrategy::Weekdays || !is_weekend {
+            days.push(day);
+        }
+        day += Duration::days(1);
+    }
+    days
+}
+
+// Splits the remaining hours over the days as "YYYY-MM-DD" and amount pairs,
+// which add up to exactly the remaining hours.
+pub fn distribute(
+    remaining: f32,
+    days: &[DayCapacity],
+    strategy: DistributionStrategy,
+) -> Result<Vec<(String, f32)>, DistributionError> {
+    if remaining <= 0.0 {
+        return Err(DistributionError::NothingToDistribute);
+    }
+
+    let units_exact = remaining * UNITS_PER_HOUR;
+    let units = units_exact.round() as u32;
+    if (units_exact - units as f32).abs() > 0.001 {
+        return Err(DistributionError::NotQuarterHours { remaining });
+    }
+
+    if days.is_empty() {
+        return Err(DistributionError::NoEligibleDays);
+    }
+
+    let allocation = match strategy {
+        DistributionStrategy::Even | DistributionStrategy::Weekdays => {
+            let count = days.len() as u32;
+            let allocation: Vec<u32> = (0..count)
+                .map(|index| units / count + u32::from(index < units % count))
+                .collect();
+
+            // Even splits must still respect the daily caps
+            if let Some((day, _)) = days
+                .iter()
+                .zip(&allocation)
+                .find(|(day, units)| **units > available_units(day))
+            {
+                return Err(DistributionError::DailyCapExceeded {
+                    day: day.day.format("%Y-%m-%d").to_string(),
+                });
+            }
+            allocation
+        }
+        DistributionStrategy::FillToDailyCap => {
+            if days.iter().any(|day| day.available.is_none()) {
+                return Err(DistributionError::NoDailyCap);
+            }
+
+            let mut left = units;
+            let allocation: Vec<u32> = days
+                .iter()
+                .map(|day| {
+                    let take = available_units(day).min(left);
+                    left -= take;
+                    take
+                })
+                .collect();
+
+            if left > 0 {
+                let capacity = (units - left) as f32 / UNITS_PER_HOUR;
+                return Err(DistributionError::InsufficientCapacity {
+                    remaining,
+                    capacity,
+                });
+            }
+            allocation
+        }
+    };
+
+    Ok(days
+        .iter()
+        .zip(allocation)
+        .filter(|(_, units)| *units > 0)
+        .map(|(day, units)| {
+            (
+                day.day.format("%Y-%m-%d").to_string(),
+                units as f32 / UNITS_PER_HOUR,
+            )
+        })
+        .collect())
+}
+
+fn available_units(day: &DayCapacity) -> u32 {
+    match day.available {
+        Some(available) => (available.max(0.0) * UNITS_PER_HOUR + 0.001).floor() as u32,
+        None => u32::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn days(from: &str, available: &[Option<f32>]) -> Vec<DayCapacity> {
+        available
+            .iter()
+            .enumerate()
+            .map(|(index, available)| DayCapacity {
+                day: date(from) + Duration::days(index as i64),
+                available: *available,
+            })
+            .collect()
+    }
+
+    fn total(allocation: &[(String, f32)]) -> f32 {
+        allocation.iter().map(|(_, amount)| amount).sum()
+    }
+
+    #[test]
+    fn weekdays_skip_the_weekend() {
+        // 2024-03-08 is a Friday
+        let candidates = candidate_days(
+            date("2024-03-08"),
+            date("2024-03-11"),
+            DistributionStrategy::Weekdays,
+        );
+        assert_eq!(candidates, vec![date("2024-03-08"), date("2024-03-11")]);
+
+        let candidates = candidate_days(
+            date("2024-03-08"),
+            date("2024-03-11"),
+            DistributionStrategy::Even,
+        );
+        assert_eq!(candidates.len(), 4);
+    }
+
+    #[test]
+    fn even_split_hands_the_leftover_quarters_to_the_first_days() {
+        let allocation = distribute(
+            1.25,
+            &days("2024-03-04", &[None; 3]),
+            DistributionStrategy::Even,
+        )
+        .unwrap();
+        assert_eq!(
+            allocation,
+            vec![
+                ("2024-03-04".to_string(), 0.5),
+                ("2024-03-05".to_string(), 0.5),
+                ("2024-03-06".to_string(), 0.25),
+            ]
+        );
+        assert_eq!(total(&allocation), 1.25);
+    }
+
+    #[test]
+    fn even_split_leaves_out_days_without_hours() {
+        let allocation = distribute(
+            0.5,
+            &days("2024-03-04", &[None; 4]),
+            DistributionStrategy::Even,
+        )
+        .unwrap();
+        assert_eq!(
+            allocation,
+            vec![
+                ("2024-03-04".to_string(), 0.25),
+                ("2024-03-05".to_string(), 0.25),
+            ]
+        );
+    }
+
+    #[test]
+    fn even_split_respects_the_daily_caps() {
+        let result = distribute(
+            4.0,
+            &days("2024-03-04", &[Some(3.0), Some(1.5)]),
+            DistributionStrategy::Even,
+        );
+        assert_eq!(
+            result,
+            Err(DistributionError::DailyCapExceeded {
+                day: "2024-03-05".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn fill_to_daily_cap_fills_the_days_in_order() {
+        let allocation = distribute(
+            5.0,
+            &days("2024-03-04", &[Some(2.0), Some(0.0), Some(4.0), Some(8.0)]),
+            DistributionStrategy::FillToDailyCap,
+        )
+        .unwrap();
+        assert_eq!(
+            allocation,
+            vec![
+                ("2024-03-04".to_string(), 2.0),
+                ("2024-03-06".to_string(), 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_to_daily_cap_reports_the_missing_capacity() {
+        let result = distribute(
+            5.0,
+            &days("2024-03-04", &[Some(2.0), Some(1.75)]),
+            DistributionStrategy::FillToDailyCap,
+        );
+        assert_eq!(
+            result,
+            Err(DistributionError::InsufficientCapacity {
+                remaining: 5.0,
+                capacity: 3.75,
+            })
+        );
+    }
+
+    #[test]
+    fn fill_to_daily_cap_requires_a_cap() {
+        let result = distribute(
+            1.0,
+            &days("2024-03-04", &[Some(2.0), None]),
+            DistributionStrategy::FillToDailyCap,
+        );
+        assert_eq!(result, Err(DistributionError::NoDailyCap));
+    }
+
+    #[test]
+    fn negative_availability_counts_as_full() {
+        let result = distribute(
+            1.0,
+            &days("2024-03-04", &[Some(-1.0), Some(1.0)]),
+            DistributionStrategy::FillToDailyCap,
+        );
+        assert_eq!(result, Ok(vec![("2024-03-05".to_string(), 1.0)]));
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_distributed() {
+        let week = days("2024-03-04", &[None; 5]);
+        assert_eq!(
+            distribute(0.0, &week, DistributionStrategy::Even),
+            Err(DistributionError::NothingToDistribute)
+        );
+        assert_eq!(
+            distribute(1.1, &week, DistributionStrategy::Even),
+            Err(DistributionError::NotQuarterHours { remaining: 1.1 })
+        );
+        assert_eq!(
+            distribute(1.0, &[], DistributionStrategy::Even),
+            Err(DistributionError::NoEligibleDays)
+        );
+    }
+}
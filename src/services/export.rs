@@ -0,0 +1,282 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+use actix_web::{error, web, web::Bytes, Error};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use mongodb::Cursor;
+use rust_xlsxwriter::{Workbook, XlsxError};
+
+use crate::api::routes_helpers::format_event_date;
+use crate::models::report::BookingExportRow;
+
+const HEADERS: [&str; 6] = [
+    "User ID",
+    "Event title",
+    "Event date",
+    "Booked on",
+    "Amount",
+    "Booking ID",
+];
+
+// Number of rows serialized into a single chunk of the CSV response
+const CSV_CHUNK_ROWS: usize = 500;
+
+// Number of rows written into the worksheet per blocking task
+const XLSX_CHUNK_ROWS: usize = 1000;
+
+const FILE_CHUNK_BYTES: usize = 64 * 1024;
+
+fn row_fields(row: &BookingExportRow) -> [String; 6] {
+    [
+        row.userId.to_hex(),
+        row.eventTitle.clone(),
+        format_event_date(row.eventDate),
+        row.toDate.clone(),
+        row.amount.to_string(),
+        row.bookingId.to_hex(),
+    ]
+}
+
+// Streams the export rows as CSV chunks while they are read from the cursor
+pub fn csv_stream(
+    cursor: Cursor<BookingExportRow>,
+) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
+    let header = csv_chunk(&[HEADERS.map(String::from)]);
+
+    let rows = stream::try_unfold(cursor, |mut cursor| async move {
+        let mut records = Vec::with_capacity(CSV_CHUNK_ROWS);
+        while records.len() < CSV_CHUNK_ROWS {
+            match cursor
+                .try_next()
+                .await
+                .map_err(error::ErrorInternalServerError)?
+            {
+                Some(row) => records.push(row_fields(&row).map(csv_cell)),
+                None => break,
+            }
+        }
+        if records.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((csv_chunk(&records)?, cursor)))
+    });
+
+    stream::once(async { header }).chain(rows)
+}
+
+// Spreadsheet apps run cells starting with one of these as formulas, e.g. from event titles.
+// Tabs and carriage returns are included as some apps skip them before the formula sign.
+// Prefixing a quote makes them text.
+fn csv_cell(field: String) -> String {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field
+    }
+}
+
+fn csv_chunk(records: &[[String; 6]]) -> Result<Bytes, Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in records {
+        writer
+            .write_record(record)
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    let buffer = writer
+        .into_inner()
+        .map_err(|err| error::ErrorInternalServerError(err.to_string()))?;
+    Ok(Bytes::from(buffer))
+}
+
+// Writes the export rows into a temporary XLSX file. The worksheet runs in constant
+// memory mode, so rows are flushed to disk as they are read from the cursor. The writes
+// run on the blocking thread pool, a chunk of rows at a time.
+pub async fn write_xlsx(mut cursor: Cursor<BookingExportRow>) -> Result<File, Error> {
+    let mut workbook = web::block(new_workbook)
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+
+    let mut row_num = 1;
+    loop {
+        let mut rows = Vec::with_capacity(XLSX_CHUNK_ROWS);
+        while rows.len() < XLSX_CHUNK_ROWS {
+            match cursor
+                .try_next()
+                .await
+                .map_err(error::ErrorInternalServerError)?
+            {
+                Some(row) => rows.push(row),
+                None => break,
+            }
+        }
+        if rows.is_empty() {
+            break;
+        }
+
+        let first_row = row_num;
+        row_num += rows.len() as u32;
+        workbook = web::block(move || -> Result<Workbook, XlsxError> {
+            write_rows(&mut workbook, first_row, &rows)?;
+            Ok(workbook)
+        })
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    }
+
+    let file = web::block(move || -> Result<File, XlsxError> {
+        let mut file = tempfile::tempfile()?;
+        workbook.save_to_writer(&mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+
+    Ok(file)
+}
+
+fn new_workbook() -> Result<Workbook, XlsxError> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet_with_constant_memory();
+    worksheet.set_name("Bookings")?;
+    for (col, header) in HEADERS.iter().enumerate() {
+        worksheet.write_string(0, col as u16, *header)?;
+    }
+    Ok(workbook)
+}
+
+// Cells are written as strings, so titles starting with "=" aren't run as formulas
+fn write_rows(
+    workbook: &mut Workbook,
+    first_row: u32,
+    rows: &[BookingExportRow],
+) -> Result<(), XlsxError> {
+    let worksheet = workbook.worksheet_from_index(0)?;
+    for (row_num, row) in (first_row..).zip(rows) {
+        let [user_id, title, event_date, to_date, _, booking_id] = row_fields(row);
+        worksheet.write_string(row_num, 0, user_id)?;
+        worksheet.write_string(row_num, 1, title)?;
+        worksheet.write_string(row_num, 2, event_date)?;
+        worksheet.write_string(row_num, 3, to_date)?;
+        worksheet.write_number(row_num, 4, row.amount)?;
+        worksheet.write_string(row_num, 5, booking_id)?;
+    }
+    Ok(())
+}
+
+// Streams a file in chunks, reading it on the blocking thread pool
+pub fn file_stream(file: File) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
+    stream::try_unfold(file, |mut file| async move {
+        let (file, chunk) = web::block(move || -> std::io::Result<(File, Vec<u8>)> {
+            let mut chunk = vec![0; FILE_CHUNK_BYTES];
+            let read = file.read(&mut chunk)?;
+            chunk.truncate(read);
+            Ok((file, chunk))
+        })
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+
+        if chunk.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((Bytes::from(chunk), file)))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+
+    fn export_row(title: &str) -> BookingExportRow {
+        BookingExportRow {
+            userId: ObjectId::parse_str("634e1f1c1f1c1f1c1f1c1f1c").unwrap(),
+            eventTitle: title.to_string(),
+            // 2024-03-04T00:00:00Z
+            eventDate: 1709510400000.0,
+            toDate: "2024-03-05".to_string(),
+            amount: 1.25,
+            bookingId: ObjectId::parse_str("634e1f1c1f1c1f1c1f1c1f1d").unwrap(),
+        }
+    }
+
+    #[test]
+    fn formula_cells_are_escaped() {
+        for field in ["=SUM(A1:A2)", "+1", "-1", "@cmd", "\t=1+1", "\r=1+1"] {
+            assert_eq!(csv_cell(field.to_string()), format!("'{field}"));
+        }
+    }
+
+    #[test]
+    fn plain_cells_are_kept() {
+        for field in ["Standup", "1.25", "2024-03-05", "a=b", "", " =1"] {
+            assert_eq!(csv_cell(field.to_string()), field);
+        }
+    }
+
+    #[test]
+    fn row_fields_follow_the_headers() {
+        assert_eq!(
+            row_fields(&export_row("Standup")),
+            [
+                "634e1f1c1f1c1f1c1f1c1f1c".to_string(),
+                "Standup".to_string(),
+                "2024-03-04".to_string(),
+                "2024-03-05".to_string(),
+                "1.25".to_string(),
+                "634e1f1c1f1c1f1c1f1c1f1d".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_chunks_quote_and_escape_the_cells() {
+        let records = [row_fields(&export_row("=HYPERLINK(\"x\", \"a,b\")")).map(csv_cell)];
+        let chunk = csv_chunk(&records).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "634e1f1c1f1c1f1c1f1c1f1c,\"'=HYPERLINK(\"\"x\"\", \"\"a,b\"\")\",2024-03-04,2024-03-05,1.25,634e1f1c1f1c1f1c1f1c1f1d\n"
+        );
+    }
+
+    #[test]
+    fn csv_chunks_keep_multiline_titles_in_one_record() {
+        let records = [
+            HEADERS.map(String::from),
+            row_fields(&export_row("Line one\nLine two")).map(csv_cell),
+        ];
+        let chunk = csv_chunk(&records).unwrap();
+        let mut reader = csv::Reader::from_reader(chunk.as_ref());
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][1], "Line one\nLine two");
+    }
+
+    #[test]
+    fn xlsx_rows_are_written_after_the_headers() {
+        let mut workbook = new_workbook().unwrap();
+        let rows = [export_row("=1+1"), export_row("Standup")];
+        write_rows(&mut workbook, 1, &rows).unwrap();
+        write_rows(&mut workbook, 3, &rows).unwrap();
+        let buffer = workbook.save_to_buffer().unwrap();
+        // XLSX files are zip archives
+        assert!(buffer.starts_with(b"PK"));
+    }
+
+    #[test]
+    fn files_are_streamed_in_chunks() {
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, &vec![b'x'; FILE_CHUNK_BYTES + 10]).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        let chunks: Vec<Bytes> = actix_web::rt::System::new()
+            .block_on(file_stream(file).try_collect())
+            .unwrap();
+        let sizes: Vec<usize> = chunks.iter().map(Bytes::len).collect();
+        assert_eq!(sizes, vec![FILE_CHUNK_BYTES, 10]);
+    }
+}
@@ -0,0 +1,329 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use serde::Deserialize;
+
+// Years after the current one that open-ended yearly recurrences are expanded to
+const RECURRENCE_HORIZON_YEARS: i32 = 10;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonHoliday {
+    Date(String),
+    Entry { date: String },
+}
+
+// Loads a holiday calendar from an iCal (.ics) or JSON file.
+// JSON files contain an array of "YYYY-MM-DD" strings or objects with a "date" key.
+pub fn load_holidays(path: &str) -> Result<HashSet<NaiveDate>, String> {
+    let content =
+        fs::read_to_string(path).map_err(|err| format!("Unable to read {path}: {err}"))?;
+
+    let is_ical = Path::new(path)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("ics"))
+        .unwrap_or(false);
+
+    if is_ical {
+        parse_ical(&content, Utc::now().year() + RECURRENCE_HORIZON_YEARS)
+    } else {
+        parse_json(&content)
+    }
+}
+
+fn parse_json(content: &str) -> Result<HashSet<NaiveDate>, String> {
+    let entries: Vec<JsonHoliday> = serde_json::from_str(content)
+        .map_err(|err| format!("Invalid JSON holiday calendar: {err}"))?;
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let date = match entry {
+                JsonHoliday::Date(date) => date,
+                JsonHoliday::Entry { date } => date,
+            };
+            NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid holiday date: {date}"))
+        })
+        .collect()
+}
+
+// Recurring events are expanded up to last_year. Only yearly rules are supported, anything
+// else is rejected rather than ignored, so the calendar doesn't silently lose holidays.
+fn parse_ical(content: &str, last_year: i32) -> Result<HashSet<NaiveDate>, String> {
+    let mut holidays = HashSet::new();
+    let mut start: Option<NaiveDate> = None;
+    let mut end: Option<NaiveDate> = None;
+    let mut end_is_date = false;
+    let mut rule: Option<String> = None;
+    let mut extra_dates: Vec<NaiveDate> = Vec::new();
+    let mut excluded_dates: HashSet<NaiveDate> = HashSet::new();
+
+    for line in content.lines().map(str::trim) {
+        if line == "BEGIN:VEVENT" {
+            start = None;
+            end = None;
+            end_is_date = false;
+            rule = None;
+            extra_dates.clear();
+            excluded_dates.clear();
+        } else if line.starts_with("DTSTART") {
+            start = Some(parse_ical_date(line)?);
+        } else if line.starts_with("DTEND") {
+            end = Some(parse_ical_date(line)?);
+            end_is_date = is_ical_date_value(line);
+        } else if line.starts_with("RRULE") {
+            rule = Some(line.split_once(':').unwrap_or_default().1.to_string());
+        } else if line.starts_with("RDATE") {
+            extra_dates.extend(parse_ical_dates(line)?);
+        } else if line.starts_with("EXDATE") {
+            excluded_dates.extend(parse_ical_dates(line)?);
+        } else if line == "END:VEVENT" {
+            let first = match start {
+                Some(first) => first,
+                None => return Err("VEVENT without DTSTART in holiday calendar".to_string()),
+            };
+            // DTEND is exclusive for all-day events, a date-time DTEND is kept as the last day
+            let last = match end {
+                Some(end) if end_is_date => end - Duration::days(1),
+                Some(end) => end,
+                None => first,
+            };
+            let mut occurrences = match &rule {
+                Some(rule) => yearly_occurrences(first, rule, last_year)?,
+                None => vec![first],
+            };
+            occurrences.extend(extra_dates.iter().copied());
+            // Every occurrence spans as many days as the first one
+            let span = last - first;
+            for occurrence in occurrences {
+                if excluded_dates.contains(&occurrence) {
+                    continue;
+                }
+                holidays.insert(occurrence);
+                let mut date = occurrence + Duration::days(1);
+                while date <= occurrence + span {
+                    holidays.insert(date);
+                    date += Duration::days(1);
+                }
+            }
+        }
+    }
+
+    Ok(holidays)
+}
+
+// Expands "RRULE:FREQ=YEARLY" with its INTERVAL, COUNT and UNTIL parts.
+// BYMONTH and BYMONTHDAY are accepted as long as they repeat the date of DTSTART.
+fn yearly_occurrences(
+    first: NaiveDate,
+    rule: &str,
+    last_year: i32,
+) -> Result<Vec<NaiveDate>, String> {
+    let unsupported = || format!("Unsupported recurrence rule in holiday calendar: {rule}");
+    let mut yearly = false;
+    let mut interval = 1;
+    let mut count: Option<usize> = None;
+    let mut until: Option<NaiveDate> = None;
+
+    for part in rule.split(';') {
+        let (name, value) = part.split_once('=').unwrap_or((part, ""));
+        match name.to_ascii_uppercase().as_str() {
+            "FREQ" if value.eq_ignore_ascii_case("YEARLY") => yearly = true,
+            "INTERVAL" => {
+                interval = match value.parse::<i32>() {
+                    Ok(interval) if interval > 0 => interval,
+                    _ => return Err(unsupported()),
+                }
+            }
+            "COUNT" => count = Some(value.parse().map_err(|_| unsupported())?),
+            "UNTIL" => until = Some(parse_ical_date_value(value).ok_or_else(unsupported)?),
+            "BYMONTH" if value == first.month().to_string() => {}
+            "BYMONTHDAY" if value == first.day().to_string() => {}
+            "WKST" => {}
+            _ => return Err(unsupported()),
+        }
+    }
+    if !yearly {
+        return Err(unsupported());
+    }
+
+    let mut occurrences = Vec::new();
+    let mut year = first.year();
+    while year <= last_year && count.is_none_or(|count| occurrences.len() < count) {
+        // February 29th only occurs in leap years
+        if let Some(date) = NaiveDate::from_ymd_opt(year, first.month(), first.day()) {
+            if until.is_some_and(|until| date > until) {
+                break;
+            }
+            occurrences.push(date);
+        }
+        year += interval;
+    }
+
+    Ok(occurrences)
+}
+
+// "DTEND;VALUE=DATE:20241226" is a date, "DTEND;VALUE=DATE-TIME:..." or "DTEND:...T..." isn't
+fn is_ical_date_value(line: &str) -> bool {
+    let (params, value) = line.split_once(':').unwrap_or((line, ""));
+    params
+        .split(';')
+        .any(|param| param.eq_ignore_ascii_case("VALUE=DATE"))
+        || (value.len() == 8 && !value.contains('T'))
+}
+
+// Handles both "DTSTART;VALUE=DATE:20241225" and "DTSTART:20241225T000000Z"
+fn parse_ical_date(line: &str) -> Result<NaiveDate, String> {
+    let value = line.rsplit(':').next().unwrap_or_default();
+    parse_ical_date_value(value).ok_or_else(|| format!("Invalid date in holiday calendar: {line}"))
+}
+
+// "EXDATE;VALUE=DATE:20251225,20261225" lists several dates
+fn parse_ical_dates(line: &str) -> Result<Vec<NaiveDate>, String> {
+    let values = line.rsplit(':').next().unwrap_or_default();
+    values
+        .split(',')
+        .map(|value| {
+            parse_ical_date_value(value)
+                .ok_or_else(|| format!("Invalid date in holiday calendar: {line}"))
+        })
+        .collect()
+}
+
+fn parse_ical_date_value(value: &str) -> Option<NaiveDate> {
+    let date = value.get(0..8).unwrap_or_default();
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn calendar(events: &[&str]) -> String {
+        let mut content = "BEGIN:VCALENDAR\r\n".to_string();
+        for event in events {
+            content.push_str(&format!("BEGIN:VEVENT\r\n{event}\r\nEND:VEVENT\r\n"));
+        }
+        content.push_str("END:VCALENDAR\r\n");
+        content
+    }
+
+    #[test]
+    fn all_day_event_excludes_dtend() {
+        let content = calendar(&["DTSTART;VALUE=DATE:20241225\r\nDTEND;VALUE=DATE:20241227"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([date("2024-12-25"), date("2024-12-26")])
+        );
+    }
+
+    #[test]
+    fn all_day_event_without_dtend_is_one_day() {
+        let content = calendar(&["DTSTART;VALUE=DATE:20241225"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(holidays, HashSet::from([date("2024-12-25")]));
+    }
+
+    #[test]
+    fn date_time_event_includes_dtend_day() {
+        let content = calendar(&["DTSTART:20241224T120000Z\r\nDTEND:20241225T180000Z"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([date("2024-12-24"), date("2024-12-25")])
+        );
+    }
+
+    #[test]
+    fn same_day_date_time_event_includes_first_day() {
+        let content = calendar(&["DTSTART:20241224T090000Z\r\nDTEND:20241224T120000Z"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(holidays, HashSet::from([date("2024-12-24")]));
+    }
+
+    #[test]
+    fn dtend_before_dtstart_keeps_first_day() {
+        let content = calendar(&["DTSTART;VALUE=DATE:20241225\r\nDTEND;VALUE=DATE:20241225"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(holidays, HashSet::from([date("2024-12-25")]));
+    }
+
+    #[test]
+    fn event_without_dtstart_is_rejected() {
+        let content = calendar(&["DTEND;VALUE=DATE:20241226"]);
+        assert!(parse_ical(&content, 2030).is_err());
+    }
+
+    #[test]
+    fn open_ended_yearly_event_is_expanded_to_the_last_year() {
+        let content = calendar(&["DTSTART;VALUE=DATE:20241225\r\nRRULE:FREQ=YEARLY"]);
+        let holidays = parse_ical(&content, 2026).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([date("2024-12-25"), date("2025-12-25"), date("2026-12-25")])
+        );
+    }
+
+    #[test]
+    fn yearly_event_respects_count_interval_and_span() {
+        let content = calendar(&[
+            "DTSTART;VALUE=DATE:20241225\r\nDTEND;VALUE=DATE:20241227\r\nRRULE:FREQ=YEARLY;INTERVAL=2;COUNT=2",
+        ]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([
+                date("2024-12-25"),
+                date("2024-12-26"),
+                date("2026-12-25"),
+                date("2026-12-26"),
+            ])
+        );
+    }
+
+    #[test]
+    fn yearly_event_respects_until_and_exdate() {
+        let content = calendar(&[
+            "DTSTART;VALUE=DATE:20240101\r\nRRULE:FREQ=YEARLY;BYMONTH=1;BYMONTHDAY=1;UNTIL=20270101T000000Z\r\nEXDATE;VALUE=DATE:20250101",
+        ]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([date("2024-01-01"), date("2026-01-01"), date("2027-01-01")])
+        );
+    }
+
+    #[test]
+    fn rdate_adds_occurrences() {
+        let content =
+            calendar(&["DTSTART;VALUE=DATE:20241225\r\nRDATE;VALUE=DATE:20250102,20250103"]);
+        let holidays = parse_ical(&content, 2030).unwrap();
+        assert_eq!(
+            holidays,
+            HashSet::from([date("2024-12-25"), date("2025-01-02"), date("2025-01-03")])
+        );
+    }
+
+    #[test]
+    fn unsupported_recurrence_is_rejected() {
+        // The 4th Thursday of November can't be expanded as a fixed date
+        let content =
+            calendar(&["DTSTART;VALUE=DATE:20241128\r\nRRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=4TH"]);
+        assert!(parse_ical(&content, 2030).is_err());
+
+        let content = calendar(&["DTSTART;VALUE=DATE:20241225\r\nRRULE:FREQ=WEEKLY"]);
+        assert!(parse_ical(&content, 2030).is_err());
+    }
+
+    #[test]
+    fn invalid_date_is_rejected() {
+        let content = calendar(&["DTSTART;VALUE=DATE:2024-12-25"]);
+        assert!(parse_ical(&content, 2030).is_err());
+    }
+}
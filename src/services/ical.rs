@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveTime, Utc};
+use futures::TryStreamExt;
+use mongodb::Cursor;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::models::report::BookingExportRow;
+
+// Bookings of a day are laid out back to back, starting at this local time
+const DAY_START_HOUR: u32 = 9;
+
+// Maximum line length in octets before folding, see RFC 5545 section 3.1
+const MAX_LINE_OCTETS: usize = 75;
+
+// Generates a new secret feed token. Only its hash is stored, see hash_feed_token.
+pub fn new_feed_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_feed_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Renders every Booking Detail as a VEVENT on its destination day, with the booked amount as duration
+pub async fn render_calendar(
+    mut cursor: Cursor<BookingExportRow>,
+) -> Result<String, mongodb::error::Error> {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut minutes_booked: HashMap<String, i64> = HashMap::new();
+
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, "PRODID:-//Booking Machine//Bookings//EN");
+    push_line(&mut calendar, "CALSCALE:GREGORIAN");
+    push_line(&mut calendar, "X-WR-CALNAME:Bookings");
+
+    while let Some(row) = cursor.try_next().await? {
+        let day = match NaiveDate::parse_from_str(&row.toDate, "%Y-%m-%d") {
+            Ok(day) => day,
+            Err(_) => continue,
+        };
+        let minutes = (row.amount * 60.0).round() as i64;
+        let offset = minutes_booked.entry(row.toDate.clone()).or_insert(0);
+        let start = day.and_time(NaiveTime::from_hms_opt(DAY_START_HOUR, 0, 0).unwrap())
+            + chrono::Duration::minutes(*offset);
+        *offset += minutes;
+
+        push_line(&mut calendar, "BEGIN:VEVENT");
+        push_line(
+            &mut calendar,
+            &format!("UID:{}@booking-machine", row.bookingId.to_hex()),
+        );
+        push_line(&mut calendar, &format!("DTSTAMP:{stamp}"));
+        push_line(
+            &mut calendar,
+            &format!("DTSTART:{}", start.format("%Y%m%dT%H%M%S")),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DURATION:PT{}H{}M", minutes / 60, minutes % 60),
+        );
+        push_line(
+            &mut calendar,
+            &format!("SUMMARY:{}", escape_text(&row.eventTitle)),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DESCRIPTION:Booked {}h", row.amount),
+        );
+        push_line(&mut calendar, "END:VEVENT");
+    }
+
+    push_line(&mut calendar, "END:VCALENDAR");
+    Ok(calendar)
+}
+
+// Line breaks of any kind become "\n", a bare CR would otherwise end the content line
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\\n")
+}
+
+// Appends a CRLF terminated content line, folding it into 75 octet chunks
+fn push_line(calendar: &mut String, line: &str) {
+    let mut octets = 0;
+    for ch in line.chars() {
+        if octets + ch.len_utf8() > MAX_LINE_OCTETS {
+            calendar.push_str("\r\n ");
+            // The leading space counts towards the next line's length
+            octets = 1;
+        }
+        calendar.push(ch);
+        octets += ch.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The content lines of a rendered calendar, with the folding undone
+    fn unfold(calendar: &str) -> String {
+        calendar.replace("\r\n ", "")
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(
+            escape_text("Review; plan, ship \\o/"),
+            "Review\\; plan\\, ship \\\\o/"
+        );
+    }
+
+    #[test]
+    fn line_breaks_are_escaped() {
+        assert_eq!(escape_text("a\nb"), "a\\nb");
+        assert_eq!(escape_text("a\r\nb"), "a\\nb");
+        assert_eq!(escape_text("a\rb"), "a\\nb");
+        assert!(!escape_text("a\r\rb\n").contains(['\r', '\n']));
+    }
+
+    #[test]
+    fn short_lines_are_not_folded() {
+        let mut calendar = String::new();
+        push_line(&mut calendar, "SUMMARY:Standup");
+        assert_eq!(calendar, "SUMMARY:Standup\r\n");
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let line = format!("SUMMARY:{}", "a".repeat(200));
+        let mut calendar = String::new();
+        push_line(&mut calendar, &line);
+
+        let lines: Vec<&str> = calendar.trim_end_matches("\r\n").split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_OCTETS));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        assert_eq!(unfold(&calendar), format!("{line}\r\n"));
+    }
+
+    #[test]
+    fn multibyte_characters_are_not_split() {
+        // "ü" takes 2 octets and "€" 3, so neither fits evenly into 75 octets
+        for text in ["ü".repeat(100), "€".repeat(100), "aü€".repeat(40)] {
+            let line = format!("SUMMARY:{text}");
+            let mut calendar = String::new();
+            push_line(&mut calendar, &line);
+
+            for folded in calendar.trim_end_matches("\r\n").split("\r\n") {
+                assert!(folded.len() <= MAX_LINE_OCTETS, "{folded} is too long");
+            }
+            assert_eq!(unfold(&calendar), format!("{line}\r\n"));
+        }
+    }
+
+    #[test]
+    fn feed_tokens_are_stored_hashed() {
+        let token = new_feed_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, new_feed_token());
+        assert_eq!(hash_feed_token(&token), hash_feed_token(&token));
+        assert_ne!(hash_feed_token(&token), token);
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::booking::{
+    apply_booking, find_owned_event, plan_booking_on_event, BookingError, BookingPlan, DailyCap,
+};
+use super::booking_rules::BookingRules;
+use super::outbox::booking_created_entries;
+use crate::api::routes_structs::BookingPayload;
+use crate::handlers::mongo::{EventBookings, MongoDB};
+use crate::models::mongo::{EventDocument, OutboxEntry};
+use crate::models::schema::ObjectIdSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RowStatus {
+    Accepted,
+    Rejected,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RowReport {
+    pub line: u64,
+    pub eventId: String,
+    pub day: String,
+    pub amount: String,
+    pub status: RowStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<ObjectIdSchema>)]
+    pub bookingId: Option<ObjectId>,
+}
+
+impl RowReport {
+    fn reject(&mut self, code: &str, reason: String) {
+        self.status = RowStatus::Rejected;
+        self.code = Some(code.to_string());
+        self.reason = Some(reason);
+        self.bookingId = None;
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub atomic: bool,
+    pub applied: bool,
+    pub acceptedRows: usize,
+    pub rejectedRows: usize,
+    pub rows: Vec<RowReport>,
+}
+
+// Parses "eventId, day, amount" rows, an optional header row is skipped
+pub fn parse_rows(body: &[u8]) -> Result<Vec<RowReport>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body);
+
+    let mut rows = vec![];
+    for (position, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |index: usize| record.get(index).unwrap_or_default().to_string();
+
+        if position == 0 && field(0).eq_ignore_ascii_case("eventId") {
+            continue;
+        }
+
+        let mut row = RowReport {
+            line: record.position().map(|pos| pos.line()).unwrap_or_default(),
+            eventId: field(0),
+            day: field(1),
+            amount: field(2),
+            status: RowStatus::Accepted,
+            code: None,
+            reason: None,
+            bookingId: None,
+        };
+        if record.len() != 3 {
+            row.reject(
+                "INVALID_ROW",
+                "Expected 3 columns: eventId, day, amount".to_string(),
+            );
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// Validates every row with the same rules as /book, including the capacity taken by the previous rows.
+// In atomic mode the accepted rows are only written if no row was rejected, otherwise every accepted row is written.
+pub async fn import_rows(
+    db: &MongoDB,
+    rules: &BookingRules,
+    user_id: &str,
+    mut rows: Vec<RowReport>,
+    atomic: bool,
+) -> Result<ImportReport, BookingError> {
+    // Would-be state of the events and days touched by the previous rows
+    let mut events: HashMap<String, (EventDocument, ObjectId)> = HashMap::new();
+    let mut booked_for_days: HashMap<String, f32> = HashMap::new();
+    let mut plans: Vec<BookingPlan> = vec![];
+    let daily_cap = rules.daily_cap_for(user_id);
+
+    for row in rows.iter_mut() {
+        if row.status == RowStatus::Rejected {
+            continue;
+        }
+
+        let payload = BookingPayload {
+            eventId: row.eventId.clone(),
+            day: row.day.clone(),
+            amount: row.amount.clone(),
+            dryRun: None,
+        };
+        if !payload.validate() {
+            row.reject(
+                "INVALID_ROW",
+                "Invalid event ID, date format or amount. Required date format: YYYY-MM-DD. Amount must be at least 0.25h".to_string(),
+            );
+            continue;
+        }
+        let amount: f32 = row.amount.parse().unwrap_or_default();
+
+        let (event, owner) = match events.get(&row.eventId) {
+            Some(owned_event) => owned_event.clone(),
+            None => match find_owned_event(db, user_id, &row.eventId).await {
+                Ok(owned_event) => owned_event,
+                Err(err @ BookingError::EventNotFound) => {
+                    row.reject(err.code(), err.to_string());
+                    continue;
+                }
+                Err(err) => return Err(err),
+            },
+        };
+
+        let daily_cap = match daily_cap {
+            Some(cap) => {
+                let booked = match booked_for_days.get(&row.day) {
+                    Some(booked) => *booked,
+                    None => match db.find_booked_amount_for_day(user_id, &row.day).await {
+                        Ok(booked) => booked,
+                        Err(err) => {
+                            return Err(BookingError::Database(
+                                "An error occurred while fetching the booked hours!",
+                                err,
+                            ))
+                        }
+                    },
+                };
+                Some(DailyCap { cap, booked })
+            }
+            None => None,
+        };
+
+        let plan = match plan_booking_on_event(event, owner, rules, daily_cap, &row.day, amount) {
+            Ok(plan) => plan,
+            Err(err) => {
+                row.reject(err.code(), err.to_string());
+                continue;
+            }
+        };
+
+        if !atomic {
+            match apply_booking(db, user_id, plan.clone()).await {
+                Ok(_) => (),
+                Err(err) => {
+                    // The cached state may no longer match the database
+                    events.remove(&row.eventId);
+                    booked_for_days.remove(&row.day);
+                    row.reject(err.code(), err.to_string());
+                    continue;
+                }
+            }
+        }
+
+        row.bookingId = Some(plan.booking_detail.id);
+        events.insert(row.eventId.clone(), (plan.event.clone(), plan.owner));
+        if let Some(DailyCap { booked, .. }) = daily_cap {
+            booked_for_days.insert(row.day.clone(), booked + amount);
+        }
+        plans.push(plan);
+    }
+
+    let rejected_rows = rows
+        .iter()
+        .filter(|row| row.status == RowStatus::Rejected)
+        .count();
+
+    let applied = if atomic {
+        if let Some(batch) = atomic_batch(&rows, &plans) {
+            let outbox = plans
+                .iter()
+                .flat_map(|plan| {
+                    booking_created_entries(
+                        plan.owner,
+                        &plan.event,
+                        &plan.booking_detail,
+                        plan.fully_booked,
+                    )
+                })
+                .collect::<Vec<OutboxEntry>>();
+            match db
+                .add_bookingdetails_atomically(user_id, &batch, &outbox)
+                .await
+            {
+                Ok(Some(_)) => true,
+                Ok(None) => return Err(BookingError::Conflict),
+                Err(err) => {
+                    return Err(BookingError::Database(
+                        "An error occurred while importing the bookings!",
+                        err,
+                    ))
+                }
+            }
+        } else {
+            false
+        }
+    } else {
+        !plans.is_empty()
+    };
+
+    Ok(ImportReport {
+        atomic,
+        applied,
+        acceptedRows: rows.len() - rejected_rows,
+        rejectedRows: rejected_rows,
+        rows,
+    })
+}
+
+// An atomic import is only written if every row was accepted
+fn atomic_batch(rows: &[RowReport], plans: &[BookingPlan]) -> Option<Vec<EventBookings>> {
+    let all_accepted = rows.iter().all(|row| row.status == RowStatus::Accepted);
+    if all_accepted && !plans.is_empty() {
+        Some(batch_by_event(plans))
+    } else {
+        None
+    }
+}
+
+// Groups the planned bookings per event, the last plan of an event holds its final booking state
+fn batch_by_event(plans: &[BookingPlan]) -> Vec<EventBookings> {
+    let mut batch: Vec<EventBookings> = vec![];
+    for plan in plans {
+        let position = match batch
+            .iter()
+            .position(|event_bookings| event_bookings.event_id == plan.event.id)
+        {
+            Some(position) => position,
+            None => {
+                // The plan's event already holds its Booking Detail
+                batch.push(EventBookings {
+                    event_id: plan.event.id,
+                    read_booking_count: plan
+                        .event
+                        .bookingDetails
+                        .as_ref()
+                        .map_or(0, |details| details.len() - 1),
+                    booking_details: vec![],
+                    days: vec![],
+                    duration_booked: 0.0,
+                    fully_booked: false,
+                });
+                batch.len() - 1
+            }
+        };
+
+        let event_bookings = &mut batch[position];
+        event_bookings
+            .booking_details
+            .push(plan.booking_detail.clone());
+        event_bookings.duration_booked = plan.duration_booked;
+        event_bookings.fully_booked = plan.fully_booked;
+        if let Some(day_change) = &plan.day_change {
+            if !event_bookings.days.contains(&day_change.day) {
+                event_bookings.days.push(day_change.day.clone());
+            }
+        }
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use super::*;
+    use crate::models::mongo::BookingDetail;
+
+    fn event(duration: f32, booked: &[f32]) -> EventDocument {
+        EventDocument {
+            id: ObjectId::new(),
+            title: "Standup".to_string(),
+            // 2024-03-04T00:00:00Z
+            date: 1709510400000.0,
+            logs: vec![],
+            booked: false,
+            bookingDetails: Some(
+                booked
+                    .iter()
+                    .map(|amount| BookingDetail::new("2024-03-04".to_string(), *amount))
+                    .collect(),
+            ),
+            durationBooked: Some(booked.iter().sum()),
+            day: ObjectId::new(),
+            duration,
+            updatedAt: DateTime::now(),
+        }
+    }
+
+    fn plan(event: EventDocument, day: &str, amount: f32) -> BookingPlan {
+        let rules = BookingRules {
+            allow_future: true,
+            ..Default::default()
+        };
+        plan_booking_on_event(event, ObjectId::new(), &rules, None, day, amount).unwrap()
+    }
+
+    fn accepted_row() -> RowReport {
+        parse_rows(b"634e1f1c1f1c1f1c1f1c1f1c,2024-03-04,1")
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn rows_are_parsed_and_trimmed() {
+        let rows = parse_rows(
+            b"eventId,day,amount\n634e1f1c1f1c1f1c1f1c1f1c , 2024-03-04 , 1.5\n\"634e1f1c1f1c1f1c1f1c1f1d\",2024-03-05,2",
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].eventId, "634e1f1c1f1c1f1c1f1c1f1c");
+        assert_eq!(rows[0].day, "2024-03-04");
+        assert_eq!(rows[0].amount, "1.5");
+        assert_eq!(rows[0].status, RowStatus::Accepted);
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].eventId, "634e1f1c1f1c1f1c1f1c1f1d");
+    }
+
+    #[test]
+    fn only_a_leading_header_is_skipped() {
+        let rows = parse_rows(b"EVENTID,day,amount\neventId,day,amount").unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].eventId, "eventId");
+    }
+
+    #[test]
+    fn rows_with_the_wrong_column_count_are_rejected() {
+        let rows = parse_rows(
+            b"634e1f1c1f1c1f1c1f1c1f1c,2024-03-04\n634e1f1c1f1c1f1c1f1c1f1c,2024-03-04,1,extra\n634e1f1c1f1c1f1c1f1c1f1c,2024-03-04,1",
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 3);
+        for row in &rows[..2] {
+            assert_eq!(row.status, RowStatus::Rejected);
+            assert_eq!(row.code.as_deref(), Some("INVALID_ROW"));
+            assert!(row.bookingId.is_none());
+        }
+        assert_eq!(rows[2].status, RowStatus::Accepted);
+    }
+
+    #[test]
+    fn empty_bodies_have_no_rows() {
+        assert!(parse_rows(b"").unwrap().is_empty());
+        assert!(parse_rows(b"eventId,day,amount\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        assert!(parse_rows(b"634e1f1c1f1c1f1c1f1c1f1c,\xff\xfe,1").is_err());
+    }
+
+    #[test]
+    fn plans_of_the_same_event_are_batched_together() {
+        let first = plan(event(8.0, &[1.0]), "2024-03-05", 2.0);
+        let second = plan(first.event.clone(), "2024-03-05", 1.0);
+        let third = plan(second.event.clone(), "2024-03-06", 4.0);
+        let other = plan(event(2.0, &[]), "2024-03-04", 2.0);
+
+        let batch = batch_by_event(&[first.clone(), other.clone(), second, third.clone()]);
+        assert_eq!(batch.len(), 2);
+
+        // The count the event was read with, before any of the imported rows
+        assert_eq!(batch[0].event_id, first.event.id);
+        assert_eq!(batch[0].read_booking_count, 1);
+        assert_eq!(batch[0].booking_details.len(), 3);
+        assert_eq!(batch[0].days, vec!["2024-03-05", "2024-03-06"]);
+        assert_eq!(batch[0].duration_booked, 8.0);
+        assert!(batch[0].fully_booked);
+        assert_eq!(batch[0].duration_booked, third.duration_booked);
+
+        assert_eq!(batch[1].event_id, other.event.id);
+        assert_eq!(batch[1].read_booking_count, 0);
+        assert_eq!(batch[1].booking_details.len(), 1);
+        // Bookings on the event's own day don't add the event to another day
+        assert!(batch[1].days.is_empty());
+        assert!(batch[1].fully_booked);
+    }
+
+    #[test]
+    fn atomic_imports_are_only_written_without_rejected_rows() {
+        let plans = [plan(event(8.0, &[]), "2024-03-05", 2.0)];
+
+        let rows = [accepted_row(), accepted_row()];
+        let batch = atomic_batch(&rows, &plans).unwrap();
+        assert_eq!(batch.len(), 1);
+
+        let mut rejected = accepted_row();
+        rejected.reject("INVALID_ROW", "Invalid row".to_string());
+        assert!(atomic_batch(&[accepted_row(), rejected], &plans).is_none());
+
+        assert!(atomic_batch(&rows, &[]).is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use prometheus::{
+    Counter, Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::models::mongo::OutboxEntry;
+use crate::services::outbox::DomainEvent;
+
+// Mongo operations are mostly below the default buckets' 5ms lower end
+const MONGO_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// The Prometheus metrics of the server. Clones share the same metrics, one instance is
+// created in main and handed to MongoDB and to the app as Data<Metrics>.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    mongo_operation_duration: HistogramVec,
+    auth_failures: IntCounterVec,
+    hours_booked: Counter,
+    bookings_deleted: IntCounter,
+    events_fully_booked: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("booking_machine".to_string()), None)
+            .expect("Invalid metrics prefix");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let mongo_operation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mongo_operation_duration_seconds",
+                "Latency of the MongoDB handler methods",
+            )
+            .buckets(MONGO_BUCKETS.to_vec()),
+            &["operation"],
+        )
+        .unwrap();
+        let auth_failures = IntCounterVec::new(
+            Opts::new("auth_failures_total", "Rejected tokens by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let hours_booked =
+            Counter::new("hours_booked_total", "Hours booked by new bookings").unwrap();
+        let bookings_deleted =
+            IntCounter::new("bookings_deleted_total", "Deleted bookings").unwrap();
+        let events_fully_booked = IntCounter::new(
+            "events_fully_booked_total",
+            "Events that became fully booked",
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(mongo_operation_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(auth_failures.clone())).unwrap();
+        registry.register(Box::new(hours_booked.clone())).unwrap();
+        registry
+            .register(Box::new(bookings_deleted.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(events_fully_booked.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            mongo_operation_duration,
+            auth_failures,
+            hours_booked,
+            bookings_deleted,
+            events_fully_booked,
+        }
+    }
+
+    // The route is the matched pattern, e.g. "/api/v1/bookings/{bookingId}", to keep the label set bounded
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(seconds);
+    }
+
+    // Observes the operation's latency when the returned timer is dropped
+    pub fn mongo_timer(&self, operation: &str) -> HistogramTimer {
+        self.mongo_operation_duration
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+
+    pub fn auth_failure(&self, reason: &str) {
+        self.auth_failures.with_label_values(&[reason]).inc();
+    }
+
+    // Counts the business events of a committed booking change from its outbox entries
+    pub fn record_domain_events(&self, outbox: &[OutboxEntry]) {
+        for entry in outbox {
+            if entry.eventType == DomainEvent::BookingCreated.as_str() {
+                if let Some(booking_detail) = &entry.payload.bookingDetail {
+                    self.hours_booked.inc_by(booking_detail.amount as f64);
+                }
+            } else if entry.eventType == DomainEvent::BookingDeleted.as_str() {
+                self.bookings_deleted.inc();
+            } else if entry.eventType == DomainEvent::EventFullyBooked.as_str() {
+                self.events_fully_booked.inc();
+            }
+        }
+    }
+
+    // The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).unwrap_or_default())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
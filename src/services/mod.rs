@@ -0,0 +1,13 @@
+pub mod booking;
+pub mod booking_rules;
+pub mod consistency;
+pub mod distribution;
+pub mod export;
+pub mod holidays;
+pub mod ical;
+pub mod import;
+pub mod metrics;
+pub mod notifications;
+pub mod outbox;
+pub mod shutdown;
+pub mod webhooks;
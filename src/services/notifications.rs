@@ -0,0 +1,242 @@
+use std::pin::pin;
+use std::time::Duration;
+
+use actix_web::{error, rt::time::interval, web::Bytes, Error};
+use futures::{
+    future::{self, Either},
+    stream, Stream,
+};
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
+
+use crate::models::mongo::{BookingDetail, EventDocument};
+use crate::models::schema::ObjectIdSchema;
+
+// Notifications a slow subscriber may fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 256;
+
+// Keeps idle connections from being closed by proxies
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum NotificationKind {
+    #[serde(rename = "bookingDetailAdded")]
+    Added,
+    #[serde(rename = "bookingDetailUpdated")]
+    Updated,
+    #[serde(rename = "bookingDetailRemoved")]
+    Removed,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Added => "bookingDetailAdded",
+            NotificationKind::Updated => "bookingDetailUpdated",
+            NotificationKind::Removed => "bookingDetailRemoved",
+        }
+    }
+}
+
+// A booking change of an event, along with the event's resulting booking state
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BookingNotification {
+    pub kind: NotificationKind,
+    // The owner of the event's Day, only their streams receive the notification
+    #[serde(skip)]
+    pub owner: ObjectId,
+    #[schema(value_type = ObjectIdSchema)]
+    pub eventId: ObjectId,
+    pub bookingDetail: BookingDetail,
+    pub durationBooked: f32,
+    pub booked: bool,
+}
+
+impl BookingNotification {
+    pub fn new(
+        kind: NotificationKind,
+        owner: ObjectId,
+        event: &EventDocument,
+        booking_detail: &BookingDetail,
+    ) -> Self {
+        BookingNotification {
+            kind,
+            owner,
+            eventId: event.id,
+            bookingDetail: booking_detail.clone(),
+            durationBooked: event.durationBooked.unwrap_or_default(),
+            booked: event.booked,
+        }
+    }
+}
+
+// In-process fan-out of booking changes to the connected notification streams
+pub struct Notifier {
+    sender: broadcast::Sender<BookingNotification>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Notifier { sender }
+    }
+
+    pub fn publish(&self, notification: BookingNotification) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.sender.send(notification);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BookingNotification> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The events and booking days a client subscribed to, among the ones of its user
+pub struct NotificationFilter {
+    pub owner: ObjectId,
+    pub event_ids: Vec<ObjectId>,
+    pub days: Vec<String>,
+}
+
+impl NotificationFilter {
+    fn matches(&self, notification: &BookingNotification) -> bool {
+        notification.owner == self.owner
+            && (self.event_ids.contains(&notification.eventId)
+                || self.days.contains(&notification.bookingDetail.toDate))
+    }
+}
+
+// Streams the matching notifications as server-sent events, with a comment line as keep-alive.
+// The stream ends once the channel closes on shutdown, so open connections don't hold it up.
+pub fn sse_stream(
+    receiver: broadcast::Receiver<BookingNotification>,
+    filter: NotificationFilter,
+) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
+    let state = (receiver, filter, interval(KEEP_ALIVE_INTERVAL));
+    stream::unfold(state, |(mut receiver, filter, mut keep_alive)| async move {
+        loop {
+            // Both are cancel safe, so nothing is lost when the other one completes first.
+            // None is a keep-alive tick.
+            let next = {
+                let recv = pin!(receiver.recv());
+                let tick = pin!(keep_alive.tick());
+                match future::select(recv, tick).await {
+                    Either::Left((received, _)) => Some(received),
+                    Either::Right(_) => None,
+                }
+            };
+            let frame = match next {
+                Some(Ok(notification)) => {
+                    if !filter.matches(&notification) {
+                        continue;
+                    }
+                    sse_frame(notification.kind.as_str(), &notification)
+                }
+                // The client missed some changes and should refetch its data
+                Some(Err(RecvError::Lagged(missed))) => {
+                    sse_frame("lagged", &serde_json::json!({ "missed": missed }))
+                }
+                Some(Err(RecvError::Closed)) => return None,
+                None => Ok(Bytes::from_static(b": keep-alive\n\n")),
+            };
+            return Some((frame, (receiver, filter, keep_alive)));
+        }
+    })
+}
+
+fn sse_frame<T: Serialize>(event: &str, data: &T) -> Result<Bytes, Error> {
+    let data = serde_json::to_string(data).map_err(error::ErrorInternalServerError)?;
+    Ok(Bytes::from(format!("event: {event}\ndata: {data}\n\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::DateTime;
+
+    fn event() -> EventDocument {
+        EventDocument {
+            id: ObjectId::new(),
+            title: "Standup".to_string(),
+            // 2024-03-04T00:00:00Z
+            date: 1709510400000.0,
+            logs: vec![],
+            booked: false,
+            bookingDetails: None,
+            durationBooked: Some(1.0),
+            day: ObjectId::new(),
+            duration: 2.0,
+            updatedAt: DateTime::now(),
+        }
+    }
+
+    fn notification(owner: ObjectId, event: &EventDocument, day: &str) -> BookingNotification {
+        let booking_detail = BookingDetail::new(day.to_string(), 1.0);
+        BookingNotification::new(NotificationKind::Added, owner, event, &booking_detail)
+    }
+
+    #[test]
+    fn notifications_of_other_owners_are_dropped() {
+        let owner = ObjectId::new();
+        let event = event();
+        let filter = NotificationFilter {
+            owner,
+            event_ids: vec![event.id],
+            days: vec!["2024-03-04".to_string()],
+        };
+
+        assert!(filter.matches(&notification(owner, &event, "2024-03-04")));
+        // Even a subscribed event and day don't leak another user's bookings
+        assert!(!filter.matches(&notification(ObjectId::new(), &event, "2024-03-04")));
+    }
+
+    #[test]
+    fn only_subscribed_events_match() {
+        let owner = ObjectId::new();
+        let subscribed = event();
+        let other = event();
+        let filter = NotificationFilter {
+            owner,
+            event_ids: vec![subscribed.id],
+            days: vec![],
+        };
+
+        assert!(filter.matches(&notification(owner, &subscribed, "2024-03-04")));
+        assert!(!filter.matches(&notification(owner, &other, "2024-03-04")));
+    }
+
+    #[test]
+    fn only_subscribed_days_match() {
+        let owner = ObjectId::new();
+        let event = event();
+        let filter = NotificationFilter {
+            owner,
+            event_ids: vec![],
+            days: vec!["2024-03-04".to_string()],
+        };
+
+        assert!(filter.matches(&notification(owner, &event, "2024-03-04")));
+        assert!(!filter.matches(&notification(owner, &event, "2024-03-05")));
+    }
+
+    #[test]
+    fn an_empty_filter_matches_nothing() {
+        let owner = ObjectId::new();
+        let filter = NotificationFilter {
+            owner,
+            event_ids: vec![],
+            days: vec![],
+        };
+
+        assert!(!filter.matches(&notification(owner, &event(), "2024-03-04")));
+    }
+}
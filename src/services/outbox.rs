@@ -0,0 +1,355 @@
+use std::{fs::OpenOptions, io::Write, time::Duration};
+
+use actix_web::{web, web::Data};
+use chrono::SecondsFormat;
+use futures_util::future::LocalBoxFuture;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::shutdown::Shutdown;
+use super::webhooks;
+use crate::config::OutboxSinkConfig;
+use crate::handlers::mongo::MongoDB;
+use crate::models::mongo::{
+    BookingDetail, BookingEventPayload, DeliveryStatus, EventDocument, OutboxEntry,
+};
+
+// How often the relay worker looks for due entries when the outbox is empty
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Time a claimed entry is hidden from other relay workers while it is being relayed
+const RELAY_LEASE_MILLIS: i64 = 60 * 1000;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Entries are retried until every sink accepted them, backing off up to the max delay
+const RETRY_BASE_DELAY_SECS: i64 = 5;
+const RETRY_MAX_DELAY_SECS: i64 = 60 * 60;
+
+pub const DEDUP_HEADER: &str = "Idempotency-Key";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainEvent {
+    BookingCreated,
+    BookingUpdated,
+    BookingDeleted,
+    EventFullyBooked,
+}
+
+impl DomainEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainEvent::BookingCreated => "booking.created",
+            DomainEvent::BookingUpdated => "booking.updated",
+            DomainEvent::BookingDeleted => "booking.deleted",
+            DomainEvent::EventFullyBooked => "event.fully_booked",
+        }
+    }
+}
+
+// Builds an outbox entry from the event's state after the booking change. The user is the
+// owner of the event's Day, not whoever made the change.
+pub fn outbox_entry(
+    domain_event: DomainEvent,
+    owner: ObjectId,
+    event: &EventDocument,
+    booking_detail: Option<&BookingDetail>,
+) -> OutboxEntry {
+    let now = DateTime::now();
+    OutboxEntry {
+        id: ObjectId::new(),
+        eventType: domain_event.as_str().to_string(),
+        payload: BookingEventPayload {
+            userId: owner,
+            eventId: event.id,
+            eventTitle: event.title.clone(),
+            bookingDetail: booking_detail.cloned(),
+            durationBooked: event.durationBooked.unwrap_or_default(),
+            duration: event.duration,
+            booked: event.booked,
+        },
+        status: DeliveryStatus::Pending,
+        deliveredSinks: vec![],
+        attempts: 0,
+        nextAttemptAt: now,
+        lastError: None,
+        createdAt: now,
+        relayedAt: None,
+    }
+}
+
+// A new Booking Detail, and the event becoming fully booked if it did
+pub fn booking_created_entries(
+    owner: ObjectId,
+    event: &EventDocument,
+    booking_detail: &BookingDetail,
+    became_fully_booked: bool,
+) -> Vec<OutboxEntry> {
+    let mut entries = vec![outbox_entry(
+        DomainEvent::BookingCreated,
+        owner,
+        event,
+        Some(booking_detail),
+    )];
+    if became_fully_booked {
+        entries.push(outbox_entry(
+            DomainEvent::EventFullyBooked,
+            owner,
+            event,
+            None,
+        ));
+    }
+    entries
+}
+
+// The JSON body handed to the sinks. The id is the outbox entry's ID, so receivers
+// can use it to drop the duplicates of retried entries.
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct Envelope<'a> {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    createdAt: String,
+    data: &'a BookingEventPayload,
+}
+
+pub fn envelope_json(entry: &OutboxEntry) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&Envelope {
+        id: entry.id.to_hex(),
+        event_type: &entry.eventType,
+        createdAt: entry
+            .createdAt
+            .to_chrono()
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        data: &entry.payload,
+    })
+}
+
+// A destination the outbox entries are relayed to. Sinks must accept the same entry
+// more than once, as an entry is retried until all sinks accepted it.
+pub trait OutboxSink {
+    fn name(&self) -> &'static str;
+    fn send<'a>(&'a self, entry: &'a OutboxEntry) -> LocalBoxFuture<'a, Result<(), String>>;
+}
+
+pub struct LogSink;
+
+impl OutboxSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn send<'a>(&'a self, entry: &'a OutboxEntry) -> LocalBoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let body = envelope_json(entry).map_err(|err| err.to_string())?;
+            info!(event_type = %entry.eventType, body, "Outbox entry");
+            Ok(())
+        })
+    }
+}
+
+// Posts the entries as JSON, any 2xx response counts as accepted
+pub struct HttpSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpSink {
+    pub fn new(url: String) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(HttpSink { url, client })
+    }
+}
+
+impl OutboxSink for HttpSink {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn send<'a>(&'a self, entry: &'a OutboxEntry) -> LocalBoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let body = envelope_json(entry).map_err(|err| err.to_string())?;
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(DEDUP_HEADER, entry.id.to_hex())
+                .body(body)
+                .send()
+                .await
+                .map_err(|err| err.to_string())?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("Sink responded with status {}", response.status()))
+            }
+        })
+    }
+}
+
+// Appends the entries to a file as JSON lines
+pub struct FileSink {
+    path: String,
+}
+
+impl FileSink {
+    pub fn new(path: String) -> Self {
+        FileSink { path }
+    }
+}
+
+impl OutboxSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn send<'a>(&'a self, entry: &'a OutboxEntry) -> LocalBoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let line = envelope_json(entry).map_err(|err| err.to_string())? + "\n";
+            let path = self.path.clone();
+            web::block(move || -> std::io::Result<()> {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(line.as_bytes())
+            })
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())
+        })
+    }
+}
+
+// Queues the entries as deliveries of the user's webhooks, see services::webhooks
+pub struct WebhookSink {
+    db: Data<MongoDB>,
+}
+
+impl WebhookSink {
+    pub fn new(db: Data<MongoDB>) -> Self {
+        WebhookSink { db }
+    }
+}
+
+impl OutboxSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    fn send<'a>(&'a self, entry: &'a OutboxEntry) -> LocalBoxFuture<'a, Result<(), String>> {
+        Box::pin(webhooks::enqueue_deliveries(&self.db, entry))
+    }
+}
+
+// Builds the sinks configured in Config::outbox_sinks
+pub fn build_sinks(
+    db: Data<MongoDB>,
+    sinks: &[OutboxSinkConfig],
+) -> Result<Vec<Box<dyn OutboxSink>>, String> {
+    sinks
+        .iter()
+        .map(|sink| -> Result<Box<dyn OutboxSink>, String> {
+            match sink {
+                OutboxSinkConfig::Log => Ok(Box::new(LogSink)),
+                OutboxSinkConfig::Http { url } => Ok(Box::new(HttpSink::new(url.clone())?)),
+                OutboxSinkConfig::File { path } => Ok(Box::new(FileSink::new(path.clone()))),
+                OutboxSinkConfig::Webhooks => Ok(Box::new(WebhookSink::new(db.clone()))),
+            }
+        })
+        .collect()
+}
+
+// Relays the outbox entries to the sinks until the shutdown is requested. Sinks that accepted
+// an entry are recorded, so a retry only goes to the sinks that failed.
+pub async fn run_relay_worker(
+    db: Data<MongoDB>,
+    sinks: Vec<Box<dyn OutboxSink>>,
+    mut shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
+        let lease_until =
+            DateTime::from_millis(DateTime::now().timestamp_millis() + RELAY_LEASE_MILLIS);
+        let entry = match db.claim_due_outbox_entry(lease_until).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                shutdown.sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            Err(err) => {
+                error!("Failed to fetch the due outbox entries: {err}");
+                shutdown.sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let mut errors = vec![];
+        for sink in &sinks {
+            if entry.deliveredSinks.iter().any(|name| name == sink.name()) {
+                continue;
+            }
+            let delivered = match sink.send(&entry).await {
+                Ok(_) => db
+                    .mark_outbox_sink_delivered(entry.id, sink.name())
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = delivered {
+                errors.push(format!("{}: {err}", sink.name()));
+            }
+        }
+
+        let recorded = if errors.is_empty() {
+            db.record_outbox_attempt(entry.id, DeliveryStatus::Delivered, DateTime::now(), None)
+                .await
+        } else {
+            let next_attempt_at = DateTime::from_millis(
+                DateTime::now().timestamp_millis() + retry_delay_secs(entry.attempts + 1) * 1000,
+            );
+            db.record_outbox_attempt(
+                entry.id,
+                DeliveryStatus::Pending,
+                next_attempt_at,
+                Some(errors.join("; ")),
+            )
+            .await
+        };
+        if let Err(err) = recorded {
+            error!("Failed to record the outbox relay attempt: {err}");
+        }
+    }
+}
+
+fn retry_delay_secs(attempts: i32) -> i64 {
+    let factor = 2_i64.saturating_pow(attempts.max(1) as u32 - 1);
+    RETRY_BASE_DELAY_SECS
+        .saturating_mul(factor)
+        .min(RETRY_MAX_DELAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_per_attempt() {
+        assert_eq!(retry_delay_secs(1), RETRY_BASE_DELAY_SECS);
+        assert_eq!(retry_delay_secs(2), RETRY_BASE_DELAY_SECS * 2);
+        assert_eq!(retry_delay_secs(4), RETRY_BASE_DELAY_SECS * 8);
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay_secs(20), RETRY_MAX_DELAY_SECS);
+        assert_eq!(retry_delay_secs(i32::MAX), RETRY_MAX_DELAY_SECS);
+    }
+
+    #[test]
+    fn retry_delay_before_the_first_attempt_is_the_base_delay() {
+        assert_eq!(retry_delay_secs(0), RETRY_BASE_DELAY_SECS);
+        assert_eq!(retry_delay_secs(-1), RETRY_BASE_DELAY_SECS);
+    }
+}
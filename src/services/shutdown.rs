@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use futures::future::select;
+use tokio::sync::watch;
+
+// Tells the background workers to stop once the HTTP server stopped, see main
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    // A dropped trigger counts as a shutdown request as well
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow() || self.0.has_changed().is_err()
+    }
+
+    // Sleeps for the duration, or less if the shutdown is requested in the meantime
+    pub async fn sleep(&mut self, duration: Duration) {
+        if self.is_requested() {
+            return;
+        }
+        let changed = Box::pin(self.0.changed());
+        let timer = Box::pin(sleep(duration));
+        select(changed, timer).await;
+    }
+}
+
+pub fn shutdown_channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger(sender), Shutdown(receiver))
+}
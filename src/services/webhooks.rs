@@ -0,0 +1,440 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use actix_web::web::Data;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rand::RngCore;
+use sha2::Sha256;
+use tokio::net::lookup_host;
+use tracing::error;
+
+use super::outbox::envelope_json;
+use super::shutdown::Shutdown;
+use crate::handlers::mongo::MongoDB;
+use crate::models::mongo::{DeliveryStatus, OutboxEntry, Webhook, WebhookDelivery};
+
+// How often the worker looks for due deliveries when the queue is empty
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Time a claimed delivery is hidden from other workers while it is being sent
+const DELIVERY_LEASE_MILLIS: i64 = 60 * 1000;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Retries back off exponentially from the base delay, up to the max delay
+const MAX_ATTEMPTS: i32 = 8;
+const RETRY_BASE_DELAY_SECS: i64 = 30;
+const RETRY_MAX_DELAY_SECS: i64 = 6 * 60 * 60;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    BookingCreated,
+    BookingDeleted,
+    EventFullyBooked,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::BookingCreated => "booking.created",
+            WebhookEvent::BookingDeleted => "booking.deleted",
+            WebhookEvent::EventFullyBooked => "event.fully_booked",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = ();
+
+    fn from_str(event: &str) -> Result<Self, Self::Err> {
+        match event {
+            "booking.created" => Ok(WebhookEvent::BookingCreated),
+            "booking.deleted" => Ok(WebhookEvent::BookingDeleted),
+            "event.fully_booked" => Ok(WebhookEvent::EventFullyBooked),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn new_webhook_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+// Stripe style signature: "t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>">"
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!(
+        "t={timestamp},v1={}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+// A webhook URL checked against the internal networks, with the addresses it resolved to
+pub struct WebhookTarget {
+    pub url: reqwest::Url,
+    pub addrs: Vec<SocketAddr>,
+}
+
+// Only the addresses reachable over the public internet are allowed as webhook targets,
+// otherwise a webhook could be pointed at the service's own network
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Shared address space (100.64.0.0/10) used by carrier-grade NAT
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_ip(IpAddr::V4(mapped)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// Resolves the webhook URL's host and rejects it if any of its addresses is internal,
+// unless private targets are allowed, e.g. for local HTTP stubs in development
+pub async fn check_webhook_url(url: &str, allow_private: bool) -> Result<WebhookTarget, String> {
+    let url = reqwest::Url::parse(url).map_err(|_| "The webhook URL is invalid".to_string())?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("The webhook URL must use http or https".to_string());
+    }
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err("The webhook URL has no host".to_string()),
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => match lookup_host((host, port)).await {
+            Ok(addrs) => addrs.collect(),
+            Err(err) => return Err(format!("Failed to resolve the webhook host {host}: {err}")),
+        },
+    };
+    if addrs.is_empty() {
+        return Err(format!("The webhook host {host} has no addresses"));
+    }
+    if !allow_private && addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+        return Err(format!(
+            "The webhook host {host} resolves to an internal address"
+        ));
+    }
+
+    Ok(WebhookTarget { url, addrs })
+}
+
+// Queues a delivery for every webhook of the user subscribed to the outbox entry's event.
+// Webhooks are scoped to their owner, there are no global subscriptions across users.
+// The body carries the outbox entry's ID, so receivers can drop the duplicates of retried entries.
+pub async fn enqueue_deliveries(db: &MongoDB, entry: &OutboxEntry) -> Result<(), String> {
+    let webhooks = db
+        .find_subscribed_webhooks(&entry.payload.userId.to_hex(), &entry.eventType)
+        .await
+        .map_err(|err| err.to_string())?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let payload = envelope_json(entry)
+        .map_err(|err| format!("Failed to serialize the webhook payload: {err}"))?;
+
+    let now = DateTime::now();
+    let deliveries: Vec<WebhookDelivery> = webhooks
+        .iter()
+        .map(|webhook| WebhookDelivery {
+            id: ObjectId::new(),
+            webhookId: webhook.id,
+            owner: webhook.owner,
+            event: entry.eventType.clone(),
+            payload: payload.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            nextAttemptAt: now,
+            lastAttemptAt: None,
+            lastStatusCode: None,
+            lastError: None,
+            createdAt: now,
+        })
+        .collect();
+
+    db.insert_webhook_deliveries(&deliveries)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+// Sends the queued deliveries until the shutdown is requested
+pub async fn run_delivery_worker(db: Data<MongoDB>, allow_private: bool, mut shutdown: Shutdown) {
+    while !shutdown.is_requested() {
+        let lease_until =
+            DateTime::from_millis(DateTime::now().timestamp_millis() + DELIVERY_LEASE_MILLIS);
+        let delivery = match db.claim_due_webhook_delivery(lease_until).await {
+            Ok(Some(delivery)) => delivery,
+            Ok(None) => {
+                shutdown.sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            Err(err) => {
+                error!("Failed to fetch the due webhook deliveries: {err}");
+                shutdown.sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let outcome = match db.find_webhook_by_id(delivery.webhookId).await {
+            Ok(Some(webhook)) if webhook.active => {
+                deliver(&webhook, &delivery, allow_private).await
+            }
+            Ok(_) => Err(DeliveryError::permanent(
+                None,
+                "Webhook was removed or deactivated".to_string(),
+            )),
+            Err(err) => Err(DeliveryError::retryable(
+                None,
+                format!("Failed to fetch the webhook: {err}"),
+            )),
+        };
+
+        let attempts = delivery.attempts + 1;
+        let recorded = match outcome {
+            Ok(status_code) => {
+                db.record_webhook_delivery_attempt(
+                    delivery.id,
+                    DeliveryStatus::Delivered,
+                    DateTime::now(),
+                    Some(status_code),
+                    None,
+                )
+                .await
+            }
+            Err(DeliveryError {
+                status_code,
+                error,
+                retry,
+            }) => {
+                let status = if !retry || attempts >= MAX_ATTEMPTS {
+                    DeliveryStatus::Failed
+                } else {
+                    DeliveryStatus::Pending
+                };
+                let next_attempt_at = DateTime::from_millis(
+                    DateTime::now().timestamp_millis() + retry_delay_secs(attempts) * 1000,
+                );
+                db.record_webhook_delivery_attempt(
+                    delivery.id,
+                    status,
+                    next_attempt_at,
+                    status_code,
+                    Some(error),
+                )
+                .await
+            }
+        };
+        if let Err(err) = recorded {
+            error!("Failed to record the webhook delivery attempt: {err}");
+        }
+    }
+}
+
+struct DeliveryError {
+    status_code: Option<i32>,
+    error: String,
+    retry: bool,
+}
+
+impl DeliveryError {
+    fn retryable(status_code: Option<i32>, error: String) -> Self {
+        DeliveryError {
+            status_code,
+            error,
+            retry: true,
+        }
+    }
+
+    fn permanent(status_code: Option<i32>, error: String) -> Self {
+        DeliveryError {
+            status_code,
+            error,
+            retry: false,
+        }
+    }
+}
+
+// Any 2xx response counts as delivered.
+// The URL is checked again before every delivery, since its DNS records may have changed since
+// the registration, and the request is pinned to the checked addresses. Redirects aren't followed.
+async fn deliver(
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+    allow_private: bool,
+) -> Result<i32, DeliveryError> {
+    let target = check_webhook_url(&webhook.url, allow_private)
+        .await
+        .map_err(|err| DeliveryError::permanent(None, err))?;
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(domain) = target.url.domain() {
+        builder = builder.resolve_to_addrs(domain, &target.addrs);
+    }
+    let client = builder
+        .build()
+        .map_err(|err| DeliveryError::retryable(None, err.to_string()))?;
+
+    let signature = sign_payload(&webhook.secret, Utc::now().timestamp(), &delivery.payload);
+
+    let response = client
+        .post(target.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.to_hex())
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|err| DeliveryError::retryable(None, err.to_string()))?;
+
+    let status_code = response.status().as_u16() as i32;
+    if response.status().is_success() {
+        Ok(status_code)
+    } else {
+        Err(DeliveryError::retryable(
+            Some(status_code),
+            format!("Webhook responded with status {status_code}"),
+        ))
+    }
+}
+
+fn retry_delay_secs(attempts: i32) -> i64 {
+    let factor = 2_i64.saturating_pow(attempts.max(1) as u32 - 1);
+    RETRY_BASE_DELAY_SECS
+        .saturating_mul(factor)
+        .min(RETRY_MAX_DELAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for value in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public_ip(ip(value)), "{value} should be internal");
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for value in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_public_ip(ip(value)), "{value} should be public");
+        }
+    }
+
+    #[test]
+    fn literal_internal_hosts_are_rejected() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://[::1]:8080/hook",
+            "https://10.0.0.5/hook",
+            "ftp://93.184.216.34/hook",
+        ] {
+            let result = actix_web::rt::System::new().block_on(check_webhook_url(url, false));
+            assert!(result.is_err(), "{url} should be rejected");
+        }
+    }
+
+    #[test]
+    fn internal_hosts_are_allowed_when_opted_in() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://[::1]/hook",
+            "http://10.0.0.5/hook",
+        ] {
+            let result = actix_web::rt::System::new().block_on(check_webhook_url(url, true));
+            assert!(result.is_ok(), "{url} should be allowed");
+        }
+        let result =
+            actix_web::rt::System::new().block_on(check_webhook_url("ftp://127.0.0.1/hook", true));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn literal_public_hosts_are_pinned() {
+        let target = actix_web::rt::System::new()
+            .block_on(check_webhook_url("https://93.184.216.34/hook", false))
+            .unwrap();
+        assert_eq!(target.addrs, vec!["93.184.216.34:443".parse().unwrap()]);
+    }
+
+    #[test]
+    fn retry_delay_backs_off_up_to_the_max_delay() {
+        assert_eq!(retry_delay_secs(0), RETRY_BASE_DELAY_SECS);
+        assert_eq!(retry_delay_secs(1), RETRY_BASE_DELAY_SECS);
+        assert_eq!(retry_delay_secs(3), RETRY_BASE_DELAY_SECS * 4);
+        assert_eq!(retry_delay_secs(MAX_ATTEMPTS * 4), RETRY_MAX_DELAY_SECS);
+    }
+
+    #[test]
+    fn signature_covers_the_timestamp_and_the_body() {
+        let signature = sign_payload("whsec_test", 1700000000, "{}");
+        assert!(signature.starts_with("t=1700000000,v1="));
+        assert_ne!(signature, sign_payload("whsec_test", 1700000001, "{}"));
+        assert_ne!(signature, sign_payload("whsec_test", 1700000000, "{ }"));
+    }
+}
@@ -0,0 +1,107 @@
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::config::{LogConfig, LogFormat};
+
+#[cfg(feature = "otlp")]
+const SERVICE_NAME: &str = "booking-machine";
+
+// Flushes the spans still buffered for the OTLP collector when the server stops
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(err) = tracer_provider.shutdown() {
+                eprintln!("Failed to flush the OTLP spans: {err}");
+            }
+        }
+    }
+}
+
+// Installs the global tracing subscriber. Records of the log crate, e.g. from actix, are
+// forwarded to it as well.
+pub fn init_telemetry(config: &LogConfig) -> Result<TelemetryGuard, String> {
+    let filter = EnvFilter::try_new(&config.filter).map_err(|err| err.to_string())?;
+
+    let (json, pretty) = match config.format {
+        LogFormat::Json => (
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+            None,
+        ),
+        LogFormat::Pretty => (None, Some(fmt::layer().pretty())),
+    };
+
+    #[cfg(feature = "otlp")]
+    let (otlp, tracer_provider) = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, tracer_provider) = otlp_layer(endpoint)?;
+            (Some(layer), Some(tracer_provider))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp: Option<tracing_subscriber::layer::Identity> = match &config.otlp_endpoint {
+        Some(_) => {
+            return Err(
+                "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT needs a build with the otlp feature"
+                    .to_string(),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(json)
+        .with(pretty)
+        .with(otlp)
+        .try_init()
+        .map_err(|err| err.to_string())?;
+
+    Ok(TelemetryGuard {
+        #[cfg(feature = "otlp")]
+        tracer_provider,
+    })
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>(
+    endpoint: &str,
+) -> Result<
+    (
+        tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>,
+        opentelemetry_sdk::trace::SdkTracerProvider,
+    ),
+    String,
+>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| format!("Failed to create the OTLP exporter: {err}"))?;
+    let tracer_provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(SERVICE_NAME)
+                .build(),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME));
+    Ok((layer, tracer_provider))
+}